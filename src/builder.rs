@@ -0,0 +1,605 @@
+use crate::config_handle::ReloadableConfig;
+use crate::error_handler::ErrorHandler;
+use crate::fields_provider::DefaultFieldsProvider;
+use crate::formatting_layer::{BunyanFormattingLayer, LayerOptions, BUNYAN_REQUIRED_FIELDS};
+use crate::level_mapping::LevelMapping;
+use crate::message_formatter::{MessageFallback, MessageFormatter};
+#[cfg(feature = "redaction")]
+use crate::redaction::{FieldPattern, Redaction};
+use crate::skip_rule::SkipRule;
+use crate::span_events::SpanEvents;
+use crate::timer::{SystemTimer, Timer};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::fmt;
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// A builder for [`BunyanFormattingLayer`], obtained via [`BunyanFormattingLayer::builder`].
+///
+/// It collects every configuration option and validates all of them at once in
+/// [`BunyanFormattingLayerBuilder::build`], which makes it a good fit for configuration
+/// loaded at runtime (e.g. from your service settings).
+///
+/// It is also the only way to set most options, e.g. [`BunyanFormattingLayerBuilder::filter`],
+/// [`BunyanFormattingLayerBuilder::span_events`] or [`BunyanFormattingLayerBuilder::timer`]:
+/// only the options that predate the builder (e.g. `serialize_span_id` or skipped fields) can
+/// be chained on a `BunyanFormattingLayer` created with [`BunyanFormattingLayer::new`].
+///
+/// ```rust
+/// use serde_json::json;
+/// use tracing_bunyan_formatter::BunyanFormattingLayer;
+///
+/// let formatting_layer = BunyanFormattingLayer::builder("tracing_example")
+///     .writer(std::io::stderr)
+///     .default_field("custom_field", json!("custom_value"))
+///     .skip_fields(["skipped"])
+///     .serialize_span_id(true)
+///     .build()
+///     .expect("Invalid formatting layer configuration");
+/// ```
+pub struct BunyanFormattingLayerBuilder<W = fn() -> std::io::Stdout> {
+    name: String,
+    make_writer: W,
+    options: BuilderOptions,
+}
+
+/// The options collected by `BunyanFormattingLayerBuilder`, except for the writer.
+#[derive(Default)]
+struct BuilderOptions {
+    /// The options that don't need to be validated.
+    layer: LayerOptions,
+    /// The reloadable options, without skipped fields: they are validated in `build`.
+    config: ReloadableConfig,
+    skip_fields: Vec<String>,
+    skip_rules: Vec<SkipRule>,
+    level_name_key: Option<String>,
+    timestamp_format: Option<String>,
+    timer: Option<Box<dyn Timer>>,
+    filter: Option<String>,
+    #[cfg(feature = "redaction")]
+    redacted_fields: Vec<(FieldPattern, Redaction)>,
+    #[cfg(feature = "redaction")]
+    redacted_values: Vec<(String, Redaction)>,
+}
+
+impl BunyanFormattingLayer<fn() -> std::io::Stdout> {
+    /// Start building a `BunyanFormattingLayer` with the given `name`.
+    ///
+    /// Records are written to stdout unless a different writer is specified using
+    /// [`BunyanFormattingLayerBuilder::writer`].
+    pub fn builder(name: impl Into<String>) -> BunyanFormattingLayerBuilder {
+        BunyanFormattingLayerBuilder {
+            name: name.into(),
+            make_writer: std::io::stdout,
+            options: BuilderOptions {
+                config: ReloadableConfig::new(HashMap::new()),
+                ..BuilderOptions::default()
+            },
+        }
+    }
+}
+
+impl<W> BunyanFormattingLayerBuilder<W> {
+    /// Set the `MakeWriter` used to get a `Write` instance to write formatted records to.
+    pub fn writer<W2>(self, make_writer: W2) -> BunyanFormattingLayerBuilder<W2>
+    where
+        W2: for<'a> MakeWriter<'a> + 'static,
+    {
+        BunyanFormattingLayerBuilder {
+            name: self.name,
+            make_writer,
+            options: self.options,
+        }
+    }
+
+    /// Add a default field to all formatted records.
+    pub fn default_field(mut self, key: impl Into<String>, value: Value) -> Self {
+        self.options.config.default_fields.insert(key.into(), value);
+        self
+    }
+
+    /// Add default fields to all formatted records.
+    pub fn default_fields(mut self, fields: HashMap<String, Value>) -> Self {
+        self.options.config.default_fields.extend(fields);
+        self
+    }
+
+    /// Add default fields computed every time a record is formatted.
+    /// See [`DefaultFieldsProvider`].
+    pub fn default_fields_provider(mut self, provider: impl DefaultFieldsProvider) -> Self {
+        self.options
+            .layer
+            .default_fields_providers
+            .push(Box::new(provider));
+        self
+    }
+
+    /// Add fields to skip when formatting. Field names can be globs, e.g. `internal.*`.
+    ///
+    /// Skipping a required core Bunyan field (e.g. `name`) is reported as an error by
+    /// [`BunyanFormattingLayerBuilder::build`].
+    pub fn skip_fields<Fields, Field>(mut self, fields: Fields) -> Self
+    where
+        Fields: IntoIterator<Item = Field>,
+        Field: Into<String>,
+    {
+        self.options
+            .skip_fields
+            .extend(fields.into_iter().map(|field| field.into()));
+        self
+    }
+
+    /// Add a rule to skip fields matching a pattern, optionally only for some targets or spans.
+    /// See [`SkipRule`].
+    ///
+    /// A rule that could skip a required core Bunyan field (e.g. `name`) is reported as an
+    /// error by [`BunyanFormattingLayerBuilder::build`].
+    pub fn skip_rule(mut self, rule: SkipRule) -> Self {
+        self.options.skip_rules.push(rule);
+        self
+    }
+
+    /// Override the `hostname` attached to all formatted records.
+    pub fn hostname(mut self, hostname: impl Into<String>) -> Self {
+        self.options.layer.hostname = hostname.into();
+        self
+    }
+
+    /// Override the `pid` attached to all formatted records.
+    pub fn pid(mut self, pid: u32) -> Self {
+        self.options.layer.pid = pid;
+        self
+    }
+
+    /// Whether to serialize span fields to events.
+    pub fn serialize_span_fields(mut self, value: bool) -> Self {
+        self.options.config.serialize_span_fields = value;
+        self
+    }
+
+    /// Whether to serialize `span_id` and `parent_span_id` field if available.
+    pub fn serialize_span_id(mut self, value: bool) -> Self {
+        self.options.config.serialize_span_id = value;
+        self
+    }
+
+    /// Whether to serialize event `span_type` field.
+    /// When set to true no custom msg format is used.
+    pub fn serialize_span_type(mut self, value: bool) -> Self {
+        self.options.config.serialize_span_type = value;
+        self
+    }
+
+    /// Whether to nest the source location of each record under a Bunyan `src` object.
+    /// See [`BunyanFormattingLayer::serialize_src`].
+    pub fn serialize_src(mut self, value: bool) -> Self {
+        self.options.layer.serialize_src = value;
+        self
+    }
+
+    /// Whether to keep track of the fields of each span in the formatting layer itself,
+    /// including the ones recorded after the span was created (`span.record(...)`).
+    ///
+    /// Span records and span fields on events are complete even without an upstream
+    /// `JsonStorageLayer`, whose storage is used instead when available. Only the own fields of
+    /// each span are stored: fields are not inherited from parent spans.
+    pub fn store_span_fields(mut self, value: bool) -> Self {
+        self.options.layer.store_span_fields = value;
+        self
+    }
+
+    /// Whether to keep the `message` of events as a structured `msg_data` field when it isn't
+    /// a string (e.g. `info!(message = 42)`), in addition to `msg`.
+    ///
+    /// Non-string messages are always rendered as JSON in `msg`, e.g. `42` or `{"code":7}`.
+    pub fn serialize_message_data(mut self, value: bool) -> Self {
+        self.options.layer.serialize_message_data = value;
+        self
+    }
+
+    /// Whether to add `span_start_time` to END records: the moment the span was first entered,
+    /// formatted like `time`.
+    ///
+    /// It is read from the [`Timer`] when the span is first entered, and omitted for spans that
+    /// were never entered.
+    pub fn serialize_span_start_time(mut self, value: bool) -> Self {
+        self.options.layer.serialize_span_start_time = value;
+        self
+    }
+
+    /// Whether to add to events a `spans` list describing all the spans they are in,
+    /// from the root span to the current one, with their `name`, `id` and own `fields`.
+    ///
+    /// Fields are collected by the formatting layer itself: it works with or without
+    /// `JsonStorageLayer`.
+    pub fn serialize_span_list(mut self, value: bool) -> Self {
+        self.options.layer.serialize_span_list = value;
+        self
+    }
+
+    /// Whether to expand dotted field names into nested objects,
+    /// e.g. `http.method` into `{"http": {"method": "GET"}}`.
+    ///
+    /// A dotted field is kept as-is, with its literal dotted name, if expanding it would
+    /// overwrite another field (e.g. if both `http` and `http.method` are set).
+    pub fn nest_dotted_fields(mut self, value: bool) -> Self {
+        self.options.layer.nest_dotted_fields = value;
+        self
+    }
+
+    /// Which span lifecycle records to emit, START and END by default. See [`SpanEvents`].
+    pub fn span_events(mut self, span_events: SpanEvents) -> Self {
+        self.options.layer.span_events = span_events;
+        self
+    }
+
+    /// Which span lifecycle records to emit for spans whose target matches `target`, a glob
+    /// (e.g. `hyper::*`), overriding [`BunyanFormattingLayerBuilder::span_events`].
+    ///
+    /// If several targets match, the first one specified is used.
+    pub fn target_span_events(
+        mut self,
+        target: impl Into<String>,
+        span_events: SpanEvents,
+    ) -> Self {
+        self.options
+            .layer
+            .target_span_events
+            .push((target.into(), span_events));
+        self
+    }
+
+    /// How `tracing` levels are converted to Bunyan levels. See [`LevelMapping`].
+    pub fn level_mapping(mut self, level_mapping: LevelMapping) -> Self {
+        self.options.layer.level_mapping = level_mapping;
+        self
+    }
+
+    /// Emit events with the field `field` set to `true` (e.g. `error!(fatal = true, ...)`)
+    /// with Bunyan's FATAL level (60), whatever their `tracing` level.
+    pub fn fatal_field(mut self, field: impl Into<String>) -> Self {
+        self.options.layer.fatal_fields.push(field.into());
+        self
+    }
+
+    /// Emit events whose target matches `target`, a glob (e.g. `my_app::shutdown::*`),
+    /// with Bunyan's FATAL level (60), whatever their `tracing` level.
+    pub fn fatal_target(mut self, target: impl Into<String>) -> Self {
+        self.options.layer.fatal_targets.push(target.into());
+        self
+    }
+
+    /// Add the name of the level of each record (e.g. `"WARN"`) under `key` (e.g. `level_name`),
+    /// next to the numeric Bunyan `level`. Events promoted to FATAL are named `FATAL`.
+    ///
+    /// The field can be dropped using [`BunyanFormattingLayerBuilder::skip_fields`] like any
+    /// other field. Using the key of a required core Bunyan field (e.g. `level`) is reported as
+    /// an error by [`BunyanFormattingLayerBuilder::build`].
+    pub fn level_name(mut self, key: impl Into<String>) -> Self {
+        self.options.level_name_key = Some(key.into());
+        self
+    }
+
+    /// Set the [`MessageFormatter`] providing the `msg` field of span records and events,
+    /// e.g. [`PlainMessageFormatter`](crate::PlainMessageFormatter) to drop the `[SPAN_NAME - EVENT]` prefixes.
+    ///
+    /// It is ignored if [`BunyanFormattingLayerBuilder::serialize_span_type`] is enabled.
+    pub fn message_formatter(mut self, message_formatter: impl MessageFormatter) -> Self {
+        self.options.layer.message_formatter = Box::new(message_formatter);
+        self
+    }
+
+    /// What to use as the message of events without a `message` field, the event target by
+    /// default. See [`MessageFallback`].
+    pub fn message_fallback(mut self, message_fallback: MessageFallback) -> Self {
+        self.options.layer.message_fallback = message_fallback;
+        self
+    }
+
+    /// Format the `time` field using a [`time` format description](https://time-rs.github.io/book/api/format-description.html)
+    /// (e.g. `"[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:3]Z"`)
+    /// instead of RFC 3339.
+    ///
+    /// The system clock is used, in UTC: see [`BunyanFormattingLayerBuilder::timer`] for
+    /// more control.
+    /// An invalid format description is reported as an error by
+    /// [`BunyanFormattingLayerBuilder::build`].
+    pub fn timestamp_format(mut self, format: impl Into<String>) -> Self {
+        self.options.timestamp_format = Some(format.into());
+        self
+    }
+
+    /// Set the [`Timer`] providing the `time` field of each record, e.g. a [`SystemTimer`]
+    /// with a local offset or a fixed clock for tests.
+    ///
+    /// It can't be combined with [`BunyanFormattingLayerBuilder::timestamp_format`].
+    pub fn timer(mut self, timer: impl Timer) -> Self {
+        self.options.timer = Some(Box::new(timer));
+        self
+    }
+
+    /// Only format the records matching `directives`, using the `RUST_LOG` syntax for targets
+    /// and levels (e.g. `warn,my_crate=debug`). Spans are matched on their own target and level.
+    ///
+    /// Unlike a filter layer (e.g. `EnvFilter`), it only applies to this formatting layer: you
+    /// can stack several `BunyanFormattingLayer`s with different thresholds on the same
+    /// subscriber. Records are still collected by the subscriber, so a global filter remains
+    /// the cheaper option to discard them everywhere.
+    ///
+    /// Invalid directives are reported as an error by [`BunyanFormattingLayerBuilder::build`].
+    pub fn filter(mut self, directives: impl Into<String>) -> Self {
+        self.options.filter = Some(directives.into());
+        self
+    }
+
+    /// Notify `handler` of the records that can't be formatted or written.
+    /// See [`ErrorHandler`].
+    pub fn error_handler(mut self, handler: impl ErrorHandler) -> Self {
+        self.options.layer.error_handler = Some(Box::new(handler));
+        self
+    }
+
+    /// Write records to `make_writer` when the primary writer fails to write them,
+    /// e.g. `std::io::stderr` when the disk holding the log file is full.
+    pub fn fallback_writer<W2>(mut self, make_writer: W2) -> Self
+    where
+        W2: for<'a> MakeWriter<'a> + Send + Sync + 'static,
+    {
+        self.options.layer.fallback_writer = Some(BoxMakeWriter::new(make_writer));
+        self
+    }
+
+    /// Redact the fields whose name matches `pattern`, including default fields, span fields
+    /// and fields nested in structured values.
+    ///
+    /// The value of the field is replaced according to `redaction`. Non-string values are
+    /// converted to their JSON representation before being redacted.
+    /// Core Bunyan fields (e.g. `msg`) can't be redacted by name.
+    ///
+    /// ```rust
+    /// use tracing_bunyan_formatter::{BunyanFormattingLayer, FieldPattern, Redaction};
+    ///
+    /// let formatting_layer = BunyanFormattingLayer::builder("test")
+    ///     .redact_field(FieldPattern::exact("password"), Redaction::Replace)
+    ///     .redact_field(FieldPattern::glob("*_token"), Redaction::Hash)
+    ///     .redact_field(FieldPattern::regex("(?i)card"), Redaction::Mask { visible: 4 })
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    #[cfg(feature = "redaction")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "redaction")))]
+    pub fn redact_field(mut self, pattern: FieldPattern, redaction: Redaction) -> Self {
+        self.options.redacted_fields.push((pattern, redaction));
+        self
+    }
+
+    /// Redact the parts of string values matching the regular expression `pattern`,
+    /// in all fields and in `msg`.
+    ///
+    /// See [`BEARER_TOKEN_PATTERN`](crate::BEARER_TOKEN_PATTERN) and
+    /// [`EMAIL_PATTERN`](crate::EMAIL_PATTERN) for common patterns.
+    ///
+    /// ```rust
+    /// use tracing_bunyan_formatter::{
+    ///     BunyanFormattingLayer, Redaction, BEARER_TOKEN_PATTERN, EMAIL_PATTERN,
+    /// };
+    ///
+    /// let formatting_layer = BunyanFormattingLayer::builder("test")
+    ///     .redact_values(BEARER_TOKEN_PATTERN, Redaction::Replace)
+    ///     .redact_values(EMAIL_PATTERN, Redaction::Hash)
+    ///     .build()
+    ///     .unwrap();
+    /// ```
+    #[cfg(feature = "redaction")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "redaction")))]
+    pub fn redact_values(mut self, pattern: impl Into<String>, redaction: Redaction) -> Self {
+        self.options
+            .redacted_values
+            .push((pattern.into(), redaction));
+        self
+    }
+}
+
+impl<W: for<'a> MakeWriter<'a> + 'static> BunyanFormattingLayerBuilder<W> {
+    /// Validate the configuration and build the `BunyanFormattingLayer`.
+    ///
+    /// All configuration errors are reported together in the returned [`BuildError`].
+    pub fn build(self) -> Result<BunyanFormattingLayer<W>, BuildError> {
+        let BuilderOptions {
+            mut layer,
+            mut config,
+            skip_fields,
+            skip_rules,
+            level_name_key,
+            timestamp_format,
+            timer,
+            filter,
+            #[cfg(feature = "redaction")]
+            redacted_fields,
+            #[cfg(feature = "redaction")]
+            redacted_values,
+        } = self.options;
+        let mut errors = Vec::new();
+
+        if self.name.is_empty() {
+            errors.push(ConfigError::EmptyName);
+        }
+
+        for field in skip_fields {
+            if let Err(field) = config.skip.add_field(field) {
+                errors.push(ConfigError::SkipCoreField(field));
+            }
+        }
+        for rule in skip_rules {
+            if let Err(field) = config.skip.add_rule(rule) {
+                errors.push(ConfigError::SkipCoreField(field));
+            }
+        }
+
+        let mut reserved_default_fields: Vec<_> = config
+            .default_fields
+            .keys()
+            .filter(|key| BUNYAN_REQUIRED_FIELDS.contains(&key.as_str()))
+            .cloned()
+            .collect();
+        reserved_default_fields.sort();
+        errors.extend(
+            reserved_default_fields
+                .into_iter()
+                .map(ConfigError::ReservedDefaultField),
+        );
+
+        match level_name_key {
+            Some(key) if BUNYAN_REQUIRED_FIELDS.contains(&key.as_str()) => {
+                errors.push(ConfigError::ReservedLevelNameKey(key));
+            }
+            key => layer.level_name_key = key,
+        }
+
+        match (timer, timestamp_format) {
+            (Some(_), Some(_)) => errors.push(ConfigError::ConflictingTimeOptions),
+            (Some(timer), None) => layer.timer = timer,
+            (None, Some(format)) => match time::format_description::parse_owned::<1>(&format) {
+                Ok(parsed) => layer.timer = Box::new(SystemTimer::utc().with_format(parsed)),
+                Err(e) => errors.push(ConfigError::InvalidTimestampFormat {
+                    format,
+                    reason: e.to_string(),
+                }),
+            },
+            (None, None) => {}
+        }
+
+        if let Some(directives) = filter {
+            match directives.parse::<Targets>() {
+                Ok(filter) => layer.filter = Some(filter),
+                Err(e) => errors.push(ConfigError::InvalidFilter {
+                    directives,
+                    reason: e.to_string(),
+                }),
+            }
+        }
+
+        #[cfg(feature = "redaction")]
+        {
+            for (pattern, redaction) in redacted_fields {
+                if let Err((pattern, e)) = layer.redactor.add_field_rule(pattern, redaction) {
+                    errors.push(ConfigError::InvalidRedactionPattern {
+                        pattern,
+                        reason: e.to_string(),
+                    });
+                }
+            }
+            for (pattern, redaction) in redacted_values {
+                if let Err((pattern, e)) = layer.redactor.add_value_rule(pattern, redaction) {
+                    errors.push(ConfigError::InvalidRedactionPattern {
+                        pattern,
+                        reason: e.to_string(),
+                    });
+                }
+            }
+        }
+
+        if !errors.is_empty() {
+            return Err(BuildError { errors });
+        }
+        Ok(BunyanFormattingLayer::with_options(
+            self.name,
+            self.make_writer,
+            config,
+            layer,
+        ))
+    }
+}
+
+/// A single invalid option detected by [`BunyanFormattingLayerBuilder::build`].
+#[non_exhaustive]
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The layer name, attached to every record as `name`, is empty.
+    EmptyName,
+    /// A required core Bunyan field was marked as skipped.
+    SkipCoreField(String),
+    /// A default field uses the key of a required core Bunyan field.
+    ReservedDefaultField(String),
+    /// The level name is attached under the key of a required core Bunyan field.
+    ReservedLevelNameKey(String),
+    /// The timestamp format description could not be parsed.
+    InvalidTimestampFormat { format: String, reason: String },
+    /// Both a timestamp format and a custom timer were specified.
+    ConflictingTimeOptions,
+    /// The filter directives could not be parsed.
+    InvalidFilter { directives: String, reason: String },
+    /// A redaction pattern is not a valid regular expression.
+    #[cfg(feature = "redaction")]
+    #[cfg_attr(docsrs, doc(cfg(feature = "redaction")))]
+    InvalidRedactionPattern { pattern: String, reason: String },
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::EmptyName => write!(f, "the layer name can't be empty"),
+            ConfigError::SkipCoreField(field) => write!(
+                f,
+                "{} is a core field in the bunyan log format, it can't be skipped",
+                field
+            ),
+            ConfigError::ReservedDefaultField(field) => write!(
+                f,
+                "{} is a core field in the bunyan log format, it can't be used as a default field",
+                field
+            ),
+            ConfigError::ReservedLevelNameKey(key) => write!(
+                f,
+                "{} is a core field in the bunyan log format, it can't be used for the level name",
+                key
+            ),
+            ConfigError::InvalidTimestampFormat { format, reason } => {
+                write!(f, "invalid timestamp format `{}`: {}", format, reason)
+            }
+            ConfigError::ConflictingTimeOptions => write!(
+                f,
+                "a timestamp format and a custom timer can't be specified at the same time"
+            ),
+            ConfigError::InvalidFilter { directives, reason } => {
+                write!(f, "invalid filter directives `{}`: {}", directives, reason)
+            }
+            #[cfg(feature = "redaction")]
+            ConfigError::InvalidRedactionPattern { pattern, reason } => {
+                write!(f, "invalid redaction pattern `{}`: {}", pattern, reason)
+            }
+        }
+    }
+}
+
+impl std::error::Error for ConfigError {}
+
+/// The error returned by [`BunyanFormattingLayerBuilder::build`], listing every invalid option.
+#[derive(Debug)]
+pub struct BuildError {
+    errors: Vec<ConfigError>,
+}
+
+impl BuildError {
+    /// All the configuration errors that were detected.
+    pub fn errors(&self) -> &[ConfigError] {
+        &self.errors
+    }
+}
+
+impl fmt::Display for BuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "invalid BunyanFormattingLayer configuration: ")?;
+        for (i, error) in self.errors.iter().enumerate() {
+            if i > 0 {
+                write!(f, "; ")?;
+            }
+            write!(f, "{}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for BuildError {}
@@ -0,0 +1,157 @@
+use crate::formatting_layer::SkipFieldError;
+use crate::skip_rule::{SkipList, SkipRule};
+use arc_swap::{ArcSwap, Guard};
+use serde_json::Value;
+use std::collections::HashMap;
+use std::sync::Arc;
+
+/// The options of a `BunyanFormattingLayer` that can be changed at runtime, through a
+/// [`BunyanConfigHandle`].
+#[derive(Clone, Debug, Default)]
+pub(crate) struct ReloadableConfig {
+    pub(crate) default_fields: HashMap<String, Value>,
+    pub(crate) skip: SkipList,
+    pub(crate) serialize_span_fields: bool,
+    pub(crate) serialize_span_id: bool,
+    pub(crate) serialize_span_type: bool,
+}
+
+impl ReloadableConfig {
+    /// The default configuration, with the given default fields.
+    pub(crate) fn new(default_fields: HashMap<String, Value>) -> Self {
+        Self {
+            default_fields,
+            skip: SkipList::default(),
+            serialize_span_fields: true,
+            serialize_span_id: false,
+            serialize_span_type: false,
+        }
+    }
+}
+
+/// A `ReloadableConfig` shared between a layer and its handles.
+///
+/// Reads are lock-free: each record loads the current configuration once and uses it
+/// throughout, so that it is formatted consistently even if the configuration changes.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SharedConfig(Arc<ArcSwap<ReloadableConfig>>);
+
+impl SharedConfig {
+    pub(crate) fn new(config: ReloadableConfig) -> Self {
+        Self(Arc::new(ArcSwap::from_pointee(config)))
+    }
+
+    pub(crate) fn load(&self) -> Guard<Arc<ReloadableConfig>> {
+        self.0.load()
+    }
+
+    /// Replace the configuration with an updated copy.
+    ///
+    /// `update` may be called more than once if the configuration is changed concurrently.
+    pub(crate) fn update(&self, update: impl Fn(&mut ReloadableConfig)) {
+        self.0.rcu(|config| {
+            let mut config = ReloadableConfig::clone(config);
+            update(&mut config);
+            config
+        });
+    }
+}
+
+/// A handle to change the configuration of a `BunyanFormattingLayer` after it has been
+/// installed, obtained via [`BunyanFormattingLayer::config_handle`](crate::BunyanFormattingLayer::config_handle).
+///
+/// Handles are cheap to clone and can be shared with e.g. an admin endpoint. Changes apply
+/// to the records formatted after they are made.
+///
+/// ```rust
+/// use serde_json::json;
+/// use std::collections::HashMap;
+/// use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
+/// use tracing_subscriber::layer::SubscriberExt;
+/// use tracing_subscriber::Registry;
+///
+/// let formatting_layer = BunyanFormattingLayer::new("tracing_example".into(), std::io::stdout);
+/// let handle = formatting_layer.config_handle();
+/// let subscriber = Registry::default()
+///     .with(JsonStorageLayer)
+///     .with(formatting_layer);
+///
+/// // Later on, e.g. from an admin endpoint
+/// handle.set_serialize_span_fields(false);
+/// handle
+///     .set_skip_fields(["payload", "internal.*"])
+///     .expect("One of the specified fields cannot be skipped");
+/// handle.set_default_fields(HashMap::from([("deployment".to_owned(), json!("green"))]));
+/// ```
+#[derive(Clone, Debug)]
+pub struct BunyanConfigHandle {
+    pub(crate) config: SharedConfig,
+}
+
+impl BunyanConfigHandle {
+    /// Replace the default fields attached to all formatted records.
+    pub fn set_default_fields(&self, fields: HashMap<String, Value>) {
+        self.config
+            .update(|config| config.default_fields = fields.clone());
+    }
+
+    /// Replace the fields to skip when formatting.
+    ///
+    /// Field names can be globs, see
+    /// [`BunyanFormattingLayer::skip_fields`](crate::BunyanFormattingLayer::skip_fields).
+    /// Skip rules are kept: see [`BunyanConfigHandle::set_skip_rules`] to replace them.
+    /// It returns an error, leaving the configuration unchanged, if you try to skip a required
+    /// core Bunyan field (e.g. `name`).
+    pub fn set_skip_fields<Fields, Field>(&self, fields: Fields) -> Result<(), SkipFieldError>
+    where
+        Fields: IntoIterator<Item = Field>,
+        Field: Into<String>,
+    {
+        let mut skip = SkipList::default();
+        for field in fields {
+            skip.add_field(field.into()).map_err(SkipFieldError)?;
+        }
+        self.config
+            .update(|config| config.skip.replace_fields(&skip));
+        Ok(())
+    }
+
+    /// Replace the rules to skip fields, e.g. only for some targets or spans.
+    /// See [`SkipRule`].
+    ///
+    /// The fields skipped by name are kept: see [`BunyanConfigHandle::set_skip_fields`] to
+    /// replace them.
+    /// It returns an error, leaving the configuration unchanged, if a rule could skip a required
+    /// core Bunyan field (e.g. `name`).
+    pub fn set_skip_rules(
+        &self,
+        rules: impl IntoIterator<Item = SkipRule>,
+    ) -> Result<(), SkipFieldError> {
+        let mut skip = SkipList::default();
+        for rule in rules {
+            skip.add_rule(rule).map_err(SkipFieldError)?;
+        }
+        self.config
+            .update(|config| config.skip.replace_rules(&skip));
+        Ok(())
+    }
+
+    /// Whether to serialize span fields to events.
+    pub fn set_serialize_span_fields(&self, value: bool) {
+        self.config
+            .update(|config| config.serialize_span_fields = value);
+    }
+
+    /// Whether to serialize `span_id` and `parent_span_id` field if available.
+    pub fn set_serialize_span_id(&self, value: bool) {
+        self.config
+            .update(|config| config.serialize_span_id = value);
+    }
+
+    /// Whether to serialize event `span_type` field.
+    /// When set to true no custom msg format is used.
+    pub fn set_serialize_span_type(&self, value: bool) {
+        self.config
+            .update(|config| config.serialize_span_type = value);
+    }
+}
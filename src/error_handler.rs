@@ -0,0 +1,45 @@
+use std::io;
+use tracing::Metadata;
+
+/// An `ErrorHandler` is notified when a record can't be formatted or written, instead of
+/// the record being silently discarded.
+///
+/// It is called with the error and the metadata of the event or span the record is about.
+/// If a [fallback writer](crate::BunyanFormattingLayerBuilder::fallback_writer) is
+/// configured, records the primary writer failed to write are then written to it, and its
+/// own failures are reported as well.
+/// Closures taking an `&io::Error` and a `&Metadata` are handlers:
+///
+/// ```rust
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use std::sync::Arc;
+/// use tracing_bunyan_formatter::BunyanFormattingLayer;
+///
+/// let lost_records = Arc::new(AtomicU64::new(0));
+/// let formatting_layer = BunyanFormattingLayer::builder("tracing_example")
+///     .error_handler({
+///         let lost_records = lost_records.clone();
+///         move |_: &std::io::Error, _: &tracing::Metadata<'_>| {
+///             lost_records.fetch_add(1, Ordering::Relaxed);
+///         }
+///     })
+///     .fallback_writer(std::io::stderr)
+///     .build()
+///     .unwrap();
+/// ```
+///
+/// Handlers are called on the thread emitting the record: they must not emit `tracing`
+/// events themselves.
+pub trait ErrorHandler: Send + Sync + 'static {
+    /// Handle the failure to format or write the record with the given metadata.
+    fn on_error(&self, error: &io::Error, metadata: &Metadata<'_>);
+}
+
+impl<F> ErrorHandler for F
+where
+    F: Fn(&io::Error, &Metadata<'_>) + Send + Sync + 'static,
+{
+    fn on_error(&self, error: &io::Error, metadata: &Metadata<'_>) {
+        self(error, metadata)
+    }
+}
@@ -0,0 +1,45 @@
+use serde_json::Value;
+use tracing::Metadata;
+
+/// A `DefaultFieldsProvider` computes default fields every time a record is formatted,
+/// unlike the static default fields set when building the layer.
+///
+/// It is called for events and span records alike, with the metadata of the event or span.
+/// Closures taking a `&Metadata` and returning the fields are providers:
+///
+/// ```rust
+/// use serde_json::json;
+/// use std::sync::atomic::{AtomicU64, Ordering};
+/// use std::sync::Arc;
+/// use tracing_bunyan_formatter::BunyanFormattingLayer;
+///
+/// let config_version = Arc::new(AtomicU64::new(1));
+/// let formatting_layer = BunyanFormattingLayer::builder("tracing_example")
+///     .default_fields_provider({
+///         let config_version = config_version.clone();
+///         move |_: &tracing::Metadata<'_>| {
+///             vec![(
+///                 "config_version".to_owned(),
+///                 json!(config_version.load(Ordering::Relaxed)),
+///             )]
+///         }
+///     })
+///     .build()
+///     .unwrap();
+/// ```
+///
+/// Like static default fields, provided fields can be skipped and can't override the required
+/// core Bunyan fields (e.g. `name`): those are silently ignored.
+pub trait DefaultFieldsProvider: Send + Sync + 'static {
+    /// The fields to add to the record with the given metadata.
+    fn fields(&self, metadata: &Metadata<'_>) -> Vec<(String, Value)>;
+}
+
+impl<F> DefaultFieldsProvider for F
+where
+    F: Fn(&Metadata<'_>) -> Vec<(String, Value)> + Send + Sync + 'static,
+{
+    fn fields(&self, metadata: &Metadata<'_>) -> Vec<(String, Value)> {
+        self(metadata)
+    }
+}
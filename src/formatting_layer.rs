@@ -1,16 +1,29 @@
+use crate::config_handle::{BunyanConfigHandle, ReloadableConfig, SharedConfig};
+use crate::error_handler::ErrorHandler;
+use crate::fields_provider::DefaultFieldsProvider;
+use crate::glob::glob_match;
+use crate::level_mapping::LevelMapping;
+use crate::message_formatter::{MessageFallback, MessageFormatter, SpanInfo};
+#[cfg(feature = "redaction")]
+use crate::redaction::Redactor;
+use crate::skip_rule::{SkipRule, SkippedFields};
+use crate::span_events::SpanEvents;
+use crate::stats::{BunyanStatsHandle, Stats};
 use crate::storage_layer::JsonStorage;
-use ahash::{HashSet, HashSetExt};
+use crate::timer::Timer;
 use serde::ser::{Serialize, SerializeMap, Serializer};
 use serde_json::Value;
 use std::borrow::Cow;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::fmt;
 use std::io::Write;
+use std::sync::Arc;
 use time::format_description::well_known::Rfc3339;
+use time::OffsetDateTime;
 use tracing::{Event, Id, Metadata, Subscriber};
-use tracing_core::metadata::Level;
-use tracing_core::span::Attributes;
-use tracing_log::AsLog;
+use tracing_core::span::{Attributes, Record};
+use tracing_subscriber::filter::Targets;
+use tracing_subscriber::fmt::writer::BoxMakeWriter;
 use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::layer::Context;
 use tracing_subscriber::registry::SpanRef;
@@ -24,43 +37,89 @@ const HOSTNAME: &str = "hostname";
 const PID: &str = "pid";
 const TIME: &str = "time";
 const MESSAGE: &str = "msg";
-const _SOURCE: &str = "src";
+const SOURCE: &str = "src";
 
-const BUNYAN_REQUIRED_FIELDS: [&str; 7] =
+pub(crate) const BUNYAN_REQUIRED_FIELDS: [&str; 7] =
     [BUNYAN_VERSION, LEVEL, NAME, HOSTNAME, PID, TIME, MESSAGE];
 
-/// Convert from log levels to Bunyan's levels.
-fn to_bunyan_level(level: &Level) -> u16 {
-    match level.as_log() {
-        log::Level::Error => 50,
-        log::Level::Warn => 40,
-        log::Level::Info => 30,
-        log::Level::Debug => 20,
-        log::Level::Trace => 10,
-    }
-}
-
 /// This layer is exclusively concerned with formatting information using the [Bunyan format](https://github.com/trentm/node-bunyan).
 /// It relies on the upstream `JsonStorageLayer` to get access to the fields attached to
 /// each span.
 #[derive(Default)]
 pub struct BunyanFormattingLayer<W: for<'a> MakeWriter<'a> + 'static> {
     make_writer: W,
-    pid: u32,
-    hostname: String,
     bunyan_version: u8,
     name: String,
-    default_fields: HashMap<String, Value>,
-    skip_fields: HashSet<String>,
-    serialize_span_fields: bool,
-    serialize_span_id: bool,
-    serialize_span_type: bool,
+    config: SharedConfig,
+    options: LayerOptions,
+    stats: Arc<Stats>,
+}
+
+/// The options of a `BunyanFormattingLayer` that are fixed once it is built, either through
+/// the chained methods of `BunyanFormattingLayer` or through `BunyanFormattingLayerBuilder`.
+pub(crate) struct LayerOptions {
+    pub(crate) pid: u32,
+    pub(crate) hostname: String,
+    pub(crate) default_fields_providers: Vec<Box<dyn DefaultFieldsProvider>>,
+    pub(crate) serialize_src: bool,
+    pub(crate) store_span_fields: bool,
+    pub(crate) serialize_message_data: bool,
+    pub(crate) serialize_span_start_time: bool,
+    pub(crate) serialize_span_list: bool,
+    pub(crate) nest_dotted_fields: bool,
+    pub(crate) span_events: SpanEvents,
+    pub(crate) target_span_events: Vec<(String, SpanEvents)>,
+    pub(crate) level_mapping: LevelMapping,
+    pub(crate) fatal_fields: Vec<String>,
+    pub(crate) fatal_targets: Vec<String>,
+    pub(crate) level_name_key: Option<String>,
+    pub(crate) message_formatter: Box<dyn MessageFormatter>,
+    pub(crate) message_fallback: MessageFallback,
+    #[cfg(feature = "redaction")]
+    pub(crate) redactor: Redactor,
+    pub(crate) timer: Box<dyn Timer>,
+    pub(crate) filter: Option<Targets>,
+    pub(crate) error_handler: Option<Box<dyn ErrorHandler>>,
+    pub(crate) fallback_writer: Option<BoxMakeWriter>,
+}
+
+impl Default for LayerOptions {
+    fn default() -> Self {
+        Self {
+            pid: std::process::id(),
+            #[cfg(feature = "hostname")]
+            hostname: gethostname::gethostname().to_string_lossy().into_owned(),
+            #[cfg(not(feature = "hostname"))]
+            hostname: Default::default(),
+            default_fields_providers: Vec::new(),
+            serialize_src: false,
+            store_span_fields: false,
+            serialize_message_data: false,
+            serialize_span_start_time: false,
+            serialize_span_list: false,
+            nest_dotted_fields: false,
+            span_events: SpanEvents::default(),
+            target_span_events: Vec::new(),
+            level_mapping: LevelMapping::default(),
+            fatal_fields: Vec::new(),
+            fatal_targets: Vec::new(),
+            level_name_key: None,
+            message_formatter: Box::default(),
+            message_fallback: MessageFallback::default(),
+            #[cfg(feature = "redaction")]
+            redactor: Redactor::default(),
+            timer: Box::default(),
+            filter: None,
+            error_handler: None,
+            fallback_writer: None,
+        }
+    }
 }
 
 /// This error will be returned in [`BunyanFormattingLayer::skip_fields`] if trying to skip a core field.
 #[non_exhaustive]
 #[derive(Debug)]
-pub struct SkipFieldError(String);
+pub struct SkipFieldError(pub(crate) String);
 
 impl fmt::Display for SkipFieldError {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
@@ -96,6 +155,11 @@ impl<W: for<'a> MakeWriter<'a> + 'static> BunyanFormattingLayer<W> {
     ///
     /// let formatting_layer = BunyanFormattingLayer::new("tracing_example".into(), || std::io::stdout());
     /// ```
+    ///
+    /// See [`BunyanFormattingLayer::builder`] to set and validate all options in one go.
+    /// Most options (e.g. filter directives, span events, timer, level mapping, message
+    /// formatter, error handler) are only available through the builder: the chained methods
+    /// of `BunyanFormattingLayer` only cover the options that predate it.
     pub fn new(name: String, make_writer: W) -> Self {
         Self::with_default_fields(name, make_writer, HashMap::new())
     }
@@ -119,46 +183,89 @@ impl<W: for<'a> MakeWriter<'a> + 'static> BunyanFormattingLayer<W> {
         name: String,
         make_writer: W,
         default_fields: HashMap<String, Value>,
+    ) -> Self {
+        Self::with_options(
+            name,
+            make_writer,
+            ReloadableConfig::new(default_fields),
+            LayerOptions::default(),
+        )
+    }
+
+    /// Create a layer from options validated by `BunyanFormattingLayerBuilder`.
+    pub(crate) fn with_options(
+        name: String,
+        make_writer: W,
+        config: ReloadableConfig,
+        options: LayerOptions,
     ) -> Self {
         Self {
             make_writer,
             name,
-            pid: std::process::id(),
-            #[cfg(feature = "hostname")]
-            hostname: gethostname::gethostname().to_string_lossy().into_owned(),
-            #[cfg(not(feature = "hostname"))]
-            hostname: Default::default(),
             bunyan_version: 0,
-            default_fields,
-            skip_fields: HashSet::new(),
-            serialize_span_fields: true,
-            serialize_span_id: false,
-            serialize_span_type: false,
+            config: SharedConfig::new(config),
+            options,
+            stats: Arc::default(),
+        }
+    }
+
+    /// Get a handle to change some options of this layer at runtime, once it is installed.
+    /// See [`BunyanConfigHandle`].
+    pub fn config_handle(&self) -> BunyanConfigHandle {
+        BunyanConfigHandle {
+            config: self.config.clone(),
+        }
+    }
+
+    /// Get a handle to read the counters of this layer, e.g. the number of records written.
+    /// See [`BunyanStatsHandle`].
+    pub fn stats_handle(&self) -> BunyanStatsHandle {
+        BunyanStatsHandle {
+            stats: self.stats.clone(),
         }
     }
 
     /// Whether to serialize span fields to events.
-    pub fn serialize_span_fields(mut self, value: bool) -> Self {
-        self.serialize_span_fields = value;
+    pub fn serialize_span_fields(self, value: bool) -> Self {
+        self.config
+            .update(|config| config.serialize_span_fields = value);
         self
     }
 
     /// Whether to serialize `span_id` and `parent_span_id` field if available.
-    pub fn serialize_span_id(mut self, value: bool) -> Self {
-        self.serialize_span_id = value;
+    pub fn serialize_span_id(self, value: bool) -> Self {
+        self.config
+            .update(|config| config.serialize_span_id = value);
         self
     }
 
     /// Whether to serialize event `span_type` field.
     /// When set to true no custom msg format is used.
-    pub fn serialize_span_type(mut self, value: bool) -> Self {
-        self.serialize_span_type = value;
+    pub fn serialize_span_type(self, value: bool) -> Self {
+        self.config
+            .update(|config| config.serialize_span_type = value);
         self
     }
+
+    /// Whether to nest the source location of each record under a Bunyan `src` object
+    /// (see <https://github.com/trentm/node-bunyan#src>) instead of emitting the flat
+    /// `target`, `line` and `file` fields.
+    ///
+    /// The module path is used as `func`.
+    /// `src` is an optional core field: it can be dropped using [`Self::skip_fields`].
+    pub fn serialize_src(mut self, value: bool) -> Self {
+        self.options.serialize_src = value;
+        self
+    }
+
     /// Add fields to skip when formatting with this layer.
     ///
     /// It returns an error if you try to skip a required core Bunyan field (e.g. `name`).
-    /// You can skip optional core Bunyan fields (e.g. `line`, `file`, `target`).
+    /// You can skip optional core Bunyan fields (e.g. `line`, `file`, `target`, `src`).
+    ///
+    /// Field names can be globs (e.g. `internal.*`), where `*` matches any sequence of
+    /// characters and `?` a single one. See [`Self::skip_rule`] to only skip fields for some
+    /// targets or spans.
     ///
     /// ```rust
     /// use tracing_bunyan_formatter::BunyanFormattingLayer;
@@ -168,19 +275,27 @@ impl<W: for<'a> MakeWriter<'a> + 'static> BunyanFormattingLayer<W> {
     ///     .skip_fields(skipped_fields.into_iter())
     ///     .expect("One of the specified fields cannot be skipped");
     /// ```
-    pub fn skip_fields<Fields, Field>(mut self, fields: Fields) -> Result<Self, SkipFieldError>
+    pub fn skip_fields<Fields, Field>(self, fields: Fields) -> Result<Self, SkipFieldError>
     where
         Fields: Iterator<Item = Field>,
         Field: Into<String>,
     {
+        let mut skip = self.config.load().skip.clone();
         for field in fields {
-            let field = field.into();
-            if BUNYAN_REQUIRED_FIELDS.contains(&field.as_str()) {
-                return Err(SkipFieldError(field));
-            }
-            self.skip_fields.insert(field);
+            skip.add_field(field.into()).map_err(SkipFieldError)?;
         }
+        self.config.update(|config| config.skip = skip.clone());
+        Ok(self)
+    }
 
+    /// Add a rule to skip fields matching a pattern, optionally only for some targets or spans.
+    ///
+    /// It returns an error if the rule could skip a required core Bunyan field (e.g. `name`).
+    /// See [`SkipRule`] for more details.
+    pub fn skip_rule(self, rule: SkipRule) -> Result<Self, SkipFieldError> {
+        let mut skip = self.config.load().skip.clone();
+        skip.add_rule(rule).map_err(SkipFieldError)?;
+        self.config.update(|config| config.skip = skip.clone());
         Ok(self)
     }
 
@@ -188,94 +303,326 @@ impl<W: for<'a> MakeWriter<'a> + 'static> BunyanFormattingLayer<W> {
         &self,
         map_serializer: &mut impl SerializeMap<Error = serde_json::Error>,
         message: &str,
-        level: &Level,
+        level: u16,
+        time: OffsetDateTime,
     ) -> Result<(), std::io::Error> {
         map_serializer.serialize_entry(BUNYAN_VERSION, &self.bunyan_version)?;
         map_serializer.serialize_entry(NAME, &self.name)?;
+        #[cfg(feature = "redaction")]
+        let message = self.options.redactor.redact_str(message);
         map_serializer.serialize_entry(MESSAGE, &message)?;
-        map_serializer.serialize_entry(LEVEL, &to_bunyan_level(level))?;
-        map_serializer.serialize_entry(HOSTNAME, &self.hostname)?;
-        map_serializer.serialize_entry(PID, &self.pid)?;
-        if let Ok(time) = &time::OffsetDateTime::now_utc().format(&Rfc3339) {
+        map_serializer.serialize_entry(LEVEL, &level)?;
+        map_serializer.serialize_entry(HOSTNAME, &self.options.hostname)?;
+        map_serializer.serialize_entry(PID, &self.options.pid)?;
+        if let Some(time) = &self.format_time(time) {
             map_serializer.serialize_entry(TIME, time)?;
         }
         Ok(())
     }
 
+    /// The Bunyan level of an event, promoted to FATAL if it matches a fatal field or target.
+    fn event_level(&self, event: &Event<'_>, event_visitor: &JsonStorage<'_>) -> u16 {
+        let is_fatal = self
+            .options
+            .fatal_fields
+            .iter()
+            .any(|field| event_visitor.get(field) == Some(&Value::Bool(true)))
+            || self
+                .options
+                .fatal_targets
+                .iter()
+                .any(|target| glob_match(target, event.metadata().target()));
+        if is_fatal {
+            LevelMapping::FATAL
+        } else {
+            self.options
+                .level_mapping
+                .bunyan_level(event.metadata().level())
+        }
+    }
+
+    /// The message of an event: its `message` field, rendered as JSON if it isn't a string
+    /// (e.g. `42` or `{"code":7}`), or the fallback if it is missing.
+    fn event_message(
+        &self,
+        event: &Event<'_>,
+        event_visitor: &JsonStorage<'_>,
+        skipped: &SkippedFields<'_>,
+    ) -> String {
+        match event_visitor.get("message") {
+            Some(Value::String(message)) => message.clone(),
+            Some(message) => message.to_string(),
+            None => self.fallback_message(event, event_visitor, skipped),
+        }
+    }
+
+    /// The message of an event without a `message` field, according to the fallback strategy.
+    fn fallback_message(
+        &self,
+        event: &Event<'_>,
+        event_visitor: &JsonStorage<'_>,
+        skipped: &SkippedFields<'_>,
+    ) -> String {
+        match self.options.message_fallback {
+            MessageFallback::Target => event.metadata().target().to_owned(),
+            MessageFallback::MetadataName => event.metadata().name().to_owned(),
+            MessageFallback::Fields => {
+                let mut fields: Vec<_> = event_visitor
+                    .values()
+                    .filter(|(key, _)| *key != "message" && !skipped.contains(key))
+                    .collect();
+                fields.sort_by_key(|(key, _)| *key);
+                fields
+                    .into_iter()
+                    .map(|(key, value)| {
+                        // Value rules are applied once, to the whole message.
+                        #[cfg(feature = "redaction")]
+                        if let Some(redacted) = self.options.redactor.redact_field(key, value) {
+                            return format!("{}={}", key, redacted);
+                        }
+                        match value {
+                            Value::String(s) => format!("{}={}", key, s),
+                            other => format!("{}={}", key, other),
+                        }
+                    })
+                    .collect::<Vec<_>>()
+                    .join(" ")
+            }
+            MessageFallback::Empty => String::new(),
+        }
+    }
+
+    /// Whether records with the given metadata pass the filter of this layer, if any.
+    fn is_enabled(&self, metadata: &Metadata<'_>) -> bool {
+        match &self.options.filter {
+            Some(filter) => filter.would_enable(metadata.target(), metadata.level()),
+            None => true,
+        }
+    }
+
+    /// Whether to emit the span records of type `events` for a span with the given metadata,
+    /// according to the span events policy and the filter of this layer.
+    fn emits_span_events(&self, metadata: &Metadata<'_>, events: SpanEvents) -> bool {
+        let span_events = self
+            .options
+            .target_span_events
+            .iter()
+            .find(|(target, _)| glob_match(target, metadata.target()))
+            .map_or(self.options.span_events, |(_, span_events)| *span_events);
+        span_events.contains(events) && self.is_enabled(metadata)
+    }
+
+    /// Format a timestamp using the configured timer, falling back to RFC 3339.
+    fn format_time(&self, time: OffsetDateTime) -> Option<String> {
+        self.options
+            .timer
+            .format_time(time)
+            .or_else(|_| time.format(&Rfc3339))
+            .ok()
+    }
+
     fn serialize_field<V>(
         &self,
         map_serializer: &mut impl SerializeMap<Error = serde_json::Error>,
+        skipped: &SkippedFields<'_>,
         key: &str,
         value: &V,
     ) -> Result<(), std::io::Error>
     where
         V: Serialize + ?Sized,
     {
-        if !self.skip_fields.contains(key) {
+        if !skipped.contains(key) {
+            #[cfg(feature = "redaction")]
+            if !self.options.redactor.is_empty() {
+                let value = self
+                    .options
+                    .redactor
+                    .redact(key, serde_json::to_value(value)?);
+                map_serializer.serialize_entry(key, &value)?;
+                return Ok(());
+            }
             map_serializer.serialize_entry(key, value)?;
         }
 
         Ok(())
     }
 
+    /// Serialize the default fields computed for this record by the providers, if any.
+    fn serialize_provided_fields(
+        &self,
+        map_serializer: &mut impl SerializeMap<Error = serde_json::Error>,
+        skipped: &SkippedFields<'_>,
+        metadata: &Metadata<'_>,
+    ) -> Result<(), std::io::Error> {
+        for provider in &self.options.default_fields_providers {
+            for (key, value) in provider.fields(metadata) {
+                // Make sure this key isn't reserved. If it is reserved,
+                // silently ignore
+                if !BUNYAN_REQUIRED_FIELDS.contains(&key.as_str()) {
+                    self.serialize_field(map_serializer, skipped, &key, &value)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Serialize the name of the record level (e.g. `WARN`), if enabled.
+    fn serialize_level_name(
+        &self,
+        map_serializer: &mut impl SerializeMap<Error = serde_json::Error>,
+        skipped: &SkippedFields<'_>,
+        level: u16,
+        metadata: &Metadata<'_>,
+    ) -> Result<(), std::io::Error> {
+        if let Some(key) = &self.options.level_name_key {
+            let name = LevelMapping::level_name(metadata.level(), level);
+            self.serialize_field(map_serializer, skipped, key, name)?;
+        }
+        Ok(())
+    }
+
+    /// Additional metadata useful for debugging: where the record comes from.
+    ///
+    /// It is nested under `src` (see https://github.com/trentm/node-bunyan#src ) if
+    /// `serialize_src` is enabled, otherwise it is emitted as flat top-level fields.
+    fn serialize_source(
+        &self,
+        map_serializer: &mut impl SerializeMap<Error = serde_json::Error>,
+        skipped: &SkippedFields<'_>,
+        metadata: &Metadata<'_>,
+    ) -> Result<(), std::io::Error> {
+        if self.options.serialize_src {
+            self.serialize_field(map_serializer, skipped, SOURCE, &Source(metadata))?;
+        } else {
+            self.serialize_field(map_serializer, skipped, "target", metadata.target())?;
+            self.serialize_field(map_serializer, skipped, "line", &metadata.line())?;
+            self.serialize_field(map_serializer, skipped, "file", &metadata.file())?;
+        }
+        Ok(())
+    }
+
+    /// Describe a span as an entry of the `spans` list: its name, id and own fields.
+    fn span_list_entry<S: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>>(
+        &self,
+        skipped: &SkippedFields<'_>,
+        span: &SpanRef<S>,
+    ) -> Value {
+        let mut fields = serde_json::Map::new();
+        if let Some(OwnFields(own_fields)) = span.extensions().get::<OwnFields>() {
+            for (key, value) in own_fields.values() {
+                if !skipped.contains(key) {
+                    let value = value.clone();
+                    #[cfg(feature = "redaction")]
+                    let value = self.options.redactor.redact(key, value);
+                    fields.insert(key.to_owned(), value);
+                }
+            }
+        }
+        serde_json::json!({
+            "name": span.metadata().name(),
+            "id": format_span_id(span),
+            "fields": fields,
+        })
+    }
+
     /// Given a span, it serialised it to a in-memory buffer (vector of bytes).
+    ///
+    /// `time` is the moment the record was captured, e.g. when the span was created or closed.
     fn serialize_span<S: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>>(
         &self,
         span: &SpanRef<S>,
         ty: Type,
         attrs: Option<&Attributes>,
+        time: OffsetDateTime,
     ) -> Result<Vec<u8>, std::io::Error> {
+        let config = self.config.load();
+        let skipped = config.skip.for_record(span.metadata().target(), || {
+            span.scope().map(|span| span.metadata().name()).collect()
+        });
         let mut buffer = Vec::new();
         let mut serializer = serde_json::Serializer::new(&mut buffer);
-        let mut map_serializer = serializer.serialize_map(None)?;
-        let message = if self.serialize_span_type {
+        let mut map_serializer = DottedKeysSerializer::new(
+            serializer.serialize_map(None)?,
+            self.options.nest_dotted_fields,
+        );
+        let message = if config.serialize_span_type {
             None
         } else {
-            Some(format_span_context(span, ty))
+            Some(with_span_info(span, |span| {
+                self.options.message_formatter.format_span(span, ty)
+            }))
         };
+        let level = self
+            .options
+            .level_mapping
+            .bunyan_level(span.metadata().level());
         self.serialize_bunyan_core_fields(
             &mut map_serializer,
             message.as_deref().unwrap_or(span.metadata().name()),
-            span.metadata().level(),
+            level,
+            time,
         )?;
-        // Additional metadata useful for debugging
-        // They should be nested under `src` (see https://github.com/trentm/node-bunyan#src )
-        // but `tracing` does not support nested values yet
-        self.serialize_field(&mut map_serializer, "target", span.metadata().target())?;
-        self.serialize_field(&mut map_serializer, "line", &span.metadata().line())?;
-        self.serialize_field(&mut map_serializer, "file", &span.metadata().file())?;
+        self.serialize_level_name(&mut map_serializer, &skipped, level, span.metadata())?;
+        self.serialize_source(&mut map_serializer, &skipped, span.metadata())?;
 
         // Add span type
-        if self.serialize_span_type {
+        if config.serialize_span_type {
             let span_type = format!("{}", ty);
-            self.serialize_field(&mut map_serializer, "span_type", &span_type)?;
+            self.serialize_field(&mut map_serializer, &skipped, "span_type", &span_type)?;
         }
 
         // Add span ids
-        if self.serialize_span_id {
+        if config.serialize_span_id {
             if let Some(parent_span) = &span.parent() {
                 self.serialize_field(
                     &mut map_serializer,
+                    &skipped,
                     "parent_span_id",
                     &format_span_id(parent_span),
                 )?;
             }
-            self.serialize_field(&mut map_serializer, "span_id", &format_span_id(span))?;
+            self.serialize_field(
+                &mut map_serializer,
+                &skipped,
+                "span_id",
+                &format_span_id(span),
+            )?;
+        }
+
+        // Add the moment the span was first entered
+        if self.options.serialize_span_start_time {
+            if let Type::ExitSpan = ty {
+                let start_time = span
+                    .extensions()
+                    .get::<SpanStartTime>()
+                    .and_then(|SpanStartTime(start_time)| self.format_time(*start_time));
+                if let Some(start_time) = start_time {
+                    self.serialize_field(
+                        &mut map_serializer,
+                        &skipped,
+                        "span_start_time",
+                        &start_time,
+                    )?;
+                }
+            }
         }
 
         // Add all default fields
-        for (key, value) in self.default_fields.iter() {
+        for (key, value) in config.default_fields.iter() {
             // Make sure this key isn't reserved. If it is reserved,
             // silently ignore
             if !BUNYAN_REQUIRED_FIELDS.contains(&key.as_str()) {
-                self.serialize_field(&mut map_serializer, key, value)?;
+                self.serialize_field(&mut map_serializer, &skipped, key, value)?;
             }
         }
+        self.serialize_provided_fields(&mut map_serializer, &skipped, span.metadata())?;
 
         // Add fields from extension or attrs if extension is not used
         let extensions = span.extensions();
         let visitor = if let Some(visitor) = extensions.get::<JsonStorage>() {
             Some(Cow::Borrowed(visitor))
+        } else if let Some(OwnFields(visitor)) = extensions.get::<OwnFields>() {
+            Some(Cow::Borrowed(visitor))
         } else if let Some(attrs) = attrs {
             let mut visitor = JsonStorage::default();
             attrs.values().record(&mut visitor);
@@ -287,8 +634,8 @@ impl<W: for<'a> MakeWriter<'a> + 'static> BunyanFormattingLayer<W> {
             for (key, value) in visitor.values() {
                 // Make sure this key isn't reserved. If it is reserved,
                 // silently ignore
-                if !BUNYAN_REQUIRED_FIELDS.contains(key) {
-                    self.serialize_field(&mut map_serializer, key, value)?;
+                if !BUNYAN_REQUIRED_FIELDS.contains(&key) {
+                    self.serialize_field(&mut map_serializer, &skipped, key, value)?;
                 }
             }
         }
@@ -304,16 +651,87 @@ impl<W: for<'a> MakeWriter<'a> + 'static> BunyanFormattingLayer<W> {
     /// If we write directly to the writer returned by self.make_writer in more than one go
     /// we can end up with broken/incoherent bits and pieces of those records when
     /// running multi-threaded/concurrent programs.
-    fn emit(&self, buffer: &[u8], meta: &Metadata<'_>) -> Result<(), std::io::Error> {
-        self.make_writer.make_writer_for(meta).write_all(buffer)
+    ///
+    /// Formatting and write errors are reported to the error handler, if any, and records
+    /// that could not be written are retried on the fallback writer, if any.
+    fn emit(
+        &self,
+        record: Result<Vec<u8>, std::io::Error>,
+        meta: &Metadata<'_>,
+        ty: Type,
+        level: u16,
+    ) {
+        let buffer = match record {
+            Ok(buffer) => buffer,
+            Err(e) => {
+                self.stats.serialization_error();
+                self.report_error(&e, meta);
+                return;
+            }
+        };
+        let mut result = self.make_writer.make_writer_for(meta).write_all(&buffer);
+        if let (Err(e), Some(fallback_writer)) = (&result, &self.options.fallback_writer) {
+            self.stats.write_error();
+            self.report_error(e, meta);
+            result = fallback_writer.make_writer_for(meta).write_all(&buffer);
+        }
+        match result {
+            Ok(()) => {
+                self.stats
+                    .record_written(meta.level(), level, ty, buffer.len());
+            }
+            Err(e) => {
+                self.stats.write_error();
+                self.stats.record_dropped();
+                self.report_error(&e, meta);
+            }
+        }
+    }
+
+    /// Serialise a span record and flush it to the writer.
+    fn emit_span<S: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>>(
+        &self,
+        span: &SpanRef<S>,
+        ty: Type,
+        attrs: Option<&Attributes>,
+        time: OffsetDateTime,
+    ) {
+        let record = self.serialize_span(span, ty, attrs, time);
+        let level = self
+            .options
+            .level_mapping
+            .bunyan_level(span.metadata().level());
+        self.emit(record, span.metadata(), ty, level);
+    }
+
+    fn report_error(&self, error: &std::io::Error, meta: &Metadata<'_>) {
+        if let Some(error_handler) = &self.options.error_handler {
+            error_handler.on_error(error, meta);
+        }
     }
 }
 
+/// The fields attached to a span itself (i.e. not inherited from its parent), stored in its
+/// extensions by `BunyanFormattingLayer` when it needs them: for the span list, or when
+/// storing span fields itself.
+struct OwnFields(JsonStorage<'static>);
+
+/// The moment a span was first entered, according to the timer of `BunyanFormattingLayer`,
+/// stored in its extensions if `serialize_span_start_time` is enabled.
+struct SpanStartTime(OffsetDateTime);
+
 /// The type of record we are dealing with: entering a span, exiting a span, an event.
 #[derive(Copy, Clone, Debug)]
+#[non_exhaustive]
 pub enum Type {
+    /// A span was created (START).
     EnterSpan,
+    /// A span was closed (END).
     ExitSpan,
+    /// A span was entered, possibly not for the first time (ENTER).
+    SpanEntered,
+    /// A span was exited, possibly to be entered again later (EXIT).
+    SpanExited,
     Event,
 }
 
@@ -322,12 +740,144 @@ impl fmt::Display for Type {
         let repr = match self {
             Type::EnterSpan => "START",
             Type::ExitSpan => "END",
+            Type::SpanEntered => "ENTER",
+            Type::SpanExited => "EXIT",
             Type::Event => "EVENT",
         };
         write!(f, "{}", repr)
     }
 }
 
+/// The Bunyan `src` object: `file`, `line` and `func` (the module path) of a record.
+/// Missing values are omitted.
+struct Source<'a>(&'a Metadata<'a>);
+
+impl Serialize for Source<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map_serializer = serializer.serialize_map(None)?;
+        if let Some(file) = self.0.file() {
+            map_serializer.serialize_entry("file", file)?;
+        }
+        if let Some(line) = self.0.line() {
+            map_serializer.serialize_entry("line", &line)?;
+        }
+        if let Some(func) = self.0.module_path() {
+            map_serializer.serialize_entry("func", func)?;
+        }
+        map_serializer.end()
+    }
+}
+
+/// A map serializer expanding dotted keys (e.g. `http.method`) into nested objects
+/// (e.g. `{"http": {"method": ..}}`), if enabled.
+///
+/// Entries with a dotted key are held back and serialized, nested, when the map ends. The other
+/// entries are serialized right away: only `serialize_entry` looks at keys.
+struct DottedKeysSerializer<M> {
+    inner: M,
+    enabled: bool,
+    keys: HashSet<String>,
+    dotted: Vec<(String, Value)>,
+}
+
+impl<M> DottedKeysSerializer<M> {
+    fn new(inner: M, enabled: bool) -> Self {
+        Self {
+            inner,
+            enabled,
+            keys: HashSet::new(),
+            dotted: Vec::new(),
+        }
+    }
+}
+
+impl<M: SerializeMap<Error = serde_json::Error>> SerializeMap for DottedKeysSerializer<M> {
+    type Ok = M::Ok;
+    type Error = serde_json::Error;
+
+    fn serialize_key<K: ?Sized + Serialize>(&mut self, key: &K) -> Result<(), Self::Error> {
+        self.inner.serialize_key(key)
+    }
+
+    fn serialize_value<V: ?Sized + Serialize>(&mut self, value: &V) -> Result<(), Self::Error> {
+        self.inner.serialize_value(value)
+    }
+
+    fn serialize_entry<K: ?Sized + Serialize, V: ?Sized + Serialize>(
+        &mut self,
+        key: &K,
+        value: &V,
+    ) -> Result<(), Self::Error> {
+        if self.enabled {
+            if let Value::String(key) = serde_json::to_value(key)? {
+                if key.contains('.') {
+                    self.dotted.push((key, serde_json::to_value(value)?));
+                } else {
+                    self.inner.serialize_entry(&key, value)?;
+                    self.keys.insert(key);
+                }
+                return Ok(());
+            }
+        }
+        self.inner.serialize_entry(key, value)
+    }
+
+    fn end(mut self) -> Result<Self::Ok, Self::Error> {
+        for (key, value) in nest_dotted_keys(&self.keys, self.dotted) {
+            self.inner.serialize_entry(&key, &value)?;
+        }
+        self.inner.end()
+    }
+}
+
+/// Expand dotted keys (e.g. `http.method`) into nested objects (e.g. `{"http": {"method": ..}}`).
+///
+/// `keys` are the keys without dots of the record, which take precedence: a dotted key is
+/// kept as-is, with its literal dotted key, if its first segment is one of them (e.g. `http`
+/// is set as well as `http.method`), or if expanding it would overwrite the value of another
+/// dotted key (e.g. `db.name` and `db.name.short` are both set).
+fn nest_dotted_keys(keys: &HashSet<String>, dotted: Vec<(String, Value)>) -> Vec<(String, Value)> {
+    let mut nested = serde_json::Map::new();
+    let mut literal = Vec::new();
+    for (key, value) in dotted {
+        let path: Vec<&str> = key.split('.').collect();
+        let result = if keys.contains(path[0]) || path.iter().any(|segment| segment.is_empty()) {
+            Err(value)
+        } else {
+            insert_nested(&mut nested, &path, value)
+        };
+        if let Err(value) = result {
+            literal.push((key, value));
+        }
+    }
+    nested.into_iter().chain(literal).collect()
+}
+
+/// Insert `value` following `path`, creating the missing intermediate objects.
+/// It gives the value back if that would overwrite an existing value.
+fn insert_nested(
+    object: &mut serde_json::Map<String, Value>,
+    path: &[&str],
+    value: Value,
+) -> Result<(), Value> {
+    match path {
+        [leaf] if !object.contains_key(*leaf) => {
+            object.insert((*leaf).to_owned(), value);
+            Ok(())
+        }
+        [segment, rest @ ..] if !rest.is_empty() => {
+            match object
+                .entry(*segment)
+                .or_insert_with(|| Value::Object(serde_json::Map::new()))
+            {
+                Value::Object(child) => insert_nested(child, rest, value),
+                _ => Err(value),
+            }
+        }
+        _ => Err(value),
+    }
+}
+
 /// Ensure consistent formatting of the span ids.
 fn format_span_id<S: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>>(
     span: &SpanRef<S>,
@@ -335,46 +885,52 @@ fn format_span_id<S: Subscriber + for<'a> tracing_subscriber::registry::LookupSp
     format!("span-{}", span.id().into_u64())
 }
 
-/// Ensure consistent formatting of the span context.
-///
-/// Example: "[AN_INTERESTING_SPAN - START]"
-fn format_span_context<S: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>>(
-    span: &SpanRef<S>,
-    ty: Type,
-) -> String {
-    format!("[{} - {}]", span.metadata().name().to_uppercase(), ty)
+/// Describe a span to the message formatter.
+fn with_span_info<S, R>(span: &SpanRef<S>, f: impl FnOnce(&SpanInfo<'_>) -> R) -> R
+where
+    S: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>,
+{
+    let extensions = span.extensions();
+    let fields = extensions.get::<JsonStorage>().or_else(|| {
+        extensions
+            .get::<OwnFields>()
+            .map(|OwnFields(fields)| fields)
+    });
+    let names_from_root = || {
+        span.scope()
+            .from_root()
+            .map(|span| span.metadata().name())
+            .collect()
+    };
+    f(&SpanInfo {
+        name: span.metadata().name(),
+        fields,
+        names_from_root: &names_from_root,
+    })
 }
 
-/// Ensure consistent formatting of event message.
+/// Ensure consistent formatting of event message, using `formatter` unless `plain_message`
+/// is set.
 ///
-/// Examples:
+/// Examples, with the default formatter:
 /// - "[AN_INTERESTING_SPAN - EVENT] My event message" (for an event with a parent span)
 /// - "My event message" (for an event without a parent span)
 fn format_event_message<S: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>>(
     current_span: &Option<SpanRef<S>>,
-    event: &Event,
+    message: String,
     event_visitor: &JsonStorage<'_>,
+    formatter: &dyn MessageFormatter,
     plain_message: bool,
 ) -> String {
-    // Extract the "message" field, if provided. Fallback to the target, if missing.
-    let mut message = event_visitor
-        .values()
-        .get("message")
-        .and_then(|v| match v {
-            Value::String(s) => Some(s.as_str()),
-            _ => None,
-        })
-        .unwrap_or_else(|| event.metadata().target())
-        .to_owned();
-
-    // If the event is in the context of a span, prepend the span name to the message.
-    if !plain_message {
-        if let Some(span) = &current_span {
-            message = format!("{} {}", format_span_context(span, Type::Event), message);
-        }
+    if plain_message {
+        return message;
+    }
+    match current_span {
+        Some(span) => with_span_info(span, |span| {
+            formatter.format_event(&message, Some(span), event_visitor)
+        }),
+        None => formatter.format_event(&message, None, event_visitor),
     }
-
-    message
 }
 
 impl<S, W> Layer<S> for BunyanFormattingLayer<W>
@@ -383,6 +939,12 @@ where
     W: for<'a> MakeWriter<'a> + 'static,
 {
     fn on_event(&self, event: &Event<'_>, ctx: Context<'_, S>) {
+        if !self.is_enabled(event.metadata()) {
+            return;
+        }
+        // Capture the timestamp first, so that it doesn't depend on how long formatting takes.
+        let time = self.options.timer.now();
+
         // Events do not necessarily happen in the context of a span, hence lookup_current
         // returns an `Option<SpanRef<_>>` instead of a `SpanRef<_>`.
         let current_span = ctx.lookup_current();
@@ -390,71 +952,108 @@ where
         let mut event_visitor = JsonStorage::default();
         event.record(&mut event_visitor);
 
+        let config = self.config.load();
+        let skipped = config.skip.for_record(event.metadata().target(), || {
+            ctx.event_scope(event)
+                .map(|scope| scope.map(|span| span.metadata().name()).collect())
+                .unwrap_or_default()
+        });
+
+        let level = self.event_level(event, &event_visitor);
+
         // Opting for a closure to use the ? operator and get more linear code.
         let format = || {
             let mut buffer = Vec::new();
 
             let mut serializer = serde_json::Serializer::new(&mut buffer);
-            let mut map_serializer = serializer.serialize_map(None)?;
+            let mut map_serializer = DottedKeysSerializer::new(
+                serializer.serialize_map(None)?,
+                self.options.nest_dotted_fields,
+            );
 
             let message = format_event_message(
                 &current_span,
-                event,
+                self.event_message(event, &event_visitor, &skipped),
                 &event_visitor,
-                self.serialize_span_type,
+                self.options.message_formatter.as_ref(),
+                config.serialize_span_type,
             );
-            self.serialize_bunyan_core_fields(
-                &mut map_serializer,
-                &message,
-                event.metadata().level(),
-            )?;
-            // Additional metadata useful for debugging
-            // They should be nested under `src` (see https://github.com/trentm/node-bunyan#src )
-            // but `tracing` does not support nested values yet
-            self.serialize_field(&mut map_serializer, "target", event.metadata().target())?;
-            self.serialize_field(&mut map_serializer, "line", &event.metadata().line())?;
-            self.serialize_field(&mut map_serializer, "file", &event.metadata().file())?;
+            self.serialize_bunyan_core_fields(&mut map_serializer, &message, level, time)?;
+            self.serialize_level_name(&mut map_serializer, &skipped, level, event.metadata())?;
+            self.serialize_source(&mut map_serializer, &skipped, event.metadata())?;
 
             // Add all default fields
-            for (key, value) in self.default_fields.iter().filter(|(key, _)| {
+            for (key, value) in config.default_fields.iter().filter(|(key, _)| {
                 key.as_str() != "message" && !BUNYAN_REQUIRED_FIELDS.contains(&key.as_str())
             }) {
-                self.serialize_field(&mut map_serializer, key, value)?;
+                self.serialize_field(&mut map_serializer, &skipped, key, value)?;
             }
+            self.serialize_provided_fields(&mut map_serializer, &skipped, event.metadata())?;
 
             // Add span ids
-            if self.serialize_span_id {
+            if config.serialize_span_id {
                 if let Some(span) = &current_span {
                     if let Some(parent_span) = &span.parent() {
                         self.serialize_field(
                             &mut map_serializer,
+                            &skipped,
                             "parent_span_id",
                             &format_span_id(parent_span),
                         )?;
                     }
-                    self.serialize_field(&mut map_serializer, "span_id", &format_span_id(span))?;
+                    self.serialize_field(
+                        &mut map_serializer,
+                        &skipped,
+                        "span_id",
+                        &format_span_id(span),
+                    )?;
+                }
+            }
+
+            // Keep the structure of non-string messages, if enabled.
+            if self.options.serialize_message_data {
+                if let Some(message) = event_visitor
+                    .get("message")
+                    .filter(|message| !message.is_string())
+                {
+                    self.serialize_field(&mut map_serializer, &skipped, "msg_data", message)?;
                 }
             }
 
             // Add all the other fields associated with the event, expect the message we already used.
             for (key, value) in event_visitor
                 .values()
-                .iter()
-                .filter(|(&key, _)| key != "message" && !BUNYAN_REQUIRED_FIELDS.contains(&key))
+                .filter(|(key, _)| *key != "message" && !BUNYAN_REQUIRED_FIELDS.contains(key))
             {
-                self.serialize_field(&mut map_serializer, key, value)?;
+                self.serialize_field(&mut map_serializer, &skipped, key, value)?;
+            }
+
+            // Add the full span hierarchy, from the root span to the current one.
+            // Span fields are already redacted by `span_list_entry`, like top-level fields:
+            // redacting the list as a whole would redact them twice (e.g. hash their hash).
+            if self.options.serialize_span_list && !skipped.contains("spans") {
+                if let Some(scope) = ctx.event_scope(event) {
+                    let spans: Vec<Value> = scope
+                        .from_root()
+                        .map(|span| self.span_list_entry(&skipped, &span))
+                        .collect();
+                    map_serializer.serialize_entry("spans", &spans)?;
+                }
             }
 
             // Add all the fields from the current span, if we have one.
-            if self.serialize_span_fields {
+            if config.serialize_span_fields {
                 if let Some(span) = &current_span {
                     let extensions = span.extensions();
-                    if let Some(visitor) = extensions.get::<JsonStorage>() {
+                    let visitor = extensions
+                        .get::<JsonStorage>()
+                        .or_else(|| extensions.get::<OwnFields>().map(|OwnFields(f)| f));
+                    if let Some(visitor) = visitor {
                         for (key, value) in visitor.values() {
                             // Make sure this key isn't reserved. If it is reserved,
                             // silently ignore
-                            if !BUNYAN_REQUIRED_FIELDS.contains(key) {
-                                self.serialize_field(&mut map_serializer, key, value)?;
+                            if !BUNYAN_REQUIRED_FIELDS.contains(&key) {
+                                self.serialize_field(&mut map_serializer, &skipped, key, value)?;
                             }
                         }
                     }
@@ -468,22 +1067,61 @@ where
         };
 
         let result: std::io::Result<Vec<u8>> = format();
-        if let Ok(formatted) = result {
-            let _ = self.emit(&formatted, event.metadata());
-        }
+        self.emit(result, event.metadata(), Type::Event, level);
     }
 
     fn on_new_span(&self, attrs: &Attributes, id: &Id, ctx: Context<'_, S>) {
+        let time = self.options.timer.now();
+        let span = ctx.span(id).expect("Span not found, this is a bug");
+        if self.options.serialize_span_list || self.options.store_span_fields {
+            let mut fields = JsonStorage::default();
+            attrs.record(&mut fields);
+            span.extensions_mut().insert(OwnFields(fields));
+        }
+        if !self.emits_span_events(span.metadata(), SpanEvents::START) {
+            return;
+        }
+        self.emit_span(&span, Type::EnterSpan, Some(attrs), time);
+    }
+
+    fn on_record(&self, id: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
+        let span = ctx.span(id).expect("Span not found, this is a bug");
+        let mut extensions = span.extensions_mut();
+        if let Some(OwnFields(fields)) = extensions.get_mut::<OwnFields>() {
+            values.record(fields);
+        }
+    }
+
+    fn on_enter(&self, id: &Id, ctx: Context<'_, S>) {
+        let time = self.options.timer.now();
+        let span = ctx.span(id).expect("Span not found, this is a bug");
+        if self.options.serialize_span_start_time {
+            let mut extensions = span.extensions_mut();
+            if extensions.get_mut::<SpanStartTime>().is_none() {
+                extensions.insert(SpanStartTime(time));
+            }
+        }
+        if !self.emits_span_events(span.metadata(), SpanEvents::ENTER) {
+            return;
+        }
+        self.emit_span(&span, Type::SpanEntered, None, time);
+    }
+
+    fn on_exit(&self, id: &Id, ctx: Context<'_, S>) {
+        let time = self.options.timer.now();
         let span = ctx.span(id).expect("Span not found, this is a bug");
-        if let Ok(serialized) = self.serialize_span(&span, Type::EnterSpan, Some(attrs)) {
-            let _ = self.emit(&serialized, span.metadata());
+        if !self.emits_span_events(span.metadata(), SpanEvents::EXIT) {
+            return;
         }
+        self.emit_span(&span, Type::SpanExited, None, time);
     }
 
     fn on_close(&self, id: Id, ctx: Context<'_, S>) {
+        let time = self.options.timer.now();
         let span = ctx.span(&id).expect("Span not found, this is a bug");
-        if let Ok(serialized) = self.serialize_span(&span, Type::ExitSpan, None) {
-            let _ = self.emit(&serialized, span.metadata());
+        if !self.emits_span_events(span.metadata(), SpanEvents::END) {
+            return;
         }
+        self.emit_span(&span, Type::ExitSpan, None, time);
     }
 }
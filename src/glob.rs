@@ -0,0 +1,33 @@
+/// Match `text` against a glob `pattern`, where `*` matches any sequence of characters
+/// (including an empty one) and `?` matches exactly one character.
+///
+/// Any other character, including `.`, matches itself.
+pub(crate) fn glob_match(pattern: &str, text: &str) -> bool {
+    // Classic greedy matching with backtracking on the last `*` seen, over byte offsets
+    // so that nothing is allocated: it runs for every field checked against a skip rule.
+    let (mut p, mut t) = (0, 0);
+    let mut last_star: Option<(usize, usize)> = None;
+    while let Some(tc) = text[t..].chars().next() {
+        match pattern[p..].chars().next() {
+            Some('*') => {
+                last_star = Some((p, t));
+                p += 1;
+            }
+            Some(pc) if pc == '?' || pc == tc => {
+                p += pc.len_utf8();
+                t += tc.len_utf8();
+            }
+            _ => match last_star {
+                Some((star_p, star_t)) => {
+                    // The star swallows one more character of the text.
+                    let star_t = star_t + text[star_t..].chars().next().map_or(1, char::len_utf8);
+                    p = star_p + 1;
+                    t = star_t;
+                    last_star = Some((star_p, star_t));
+                }
+                None => return false,
+            },
+        }
+    }
+    pattern[p..].chars().all(|c| c == '*')
+}
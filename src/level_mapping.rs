@@ -0,0 +1,72 @@
+use tracing_core::metadata::Level;
+
+/// How `tracing` levels are converted to [Bunyan levels](https://github.com/trentm/node-bunyan#levels).
+///
+/// The default mapping is TRACE=10, DEBUG=20, INFO=30, WARN=40 and ERROR=50.
+/// Bunyan's FATAL (60) has no `tracing` equivalent: events can be promoted to it using
+/// [`BunyanFormattingLayerBuilder::fatal_field`](crate::BunyanFormattingLayerBuilder::fatal_field)
+/// or [`BunyanFormattingLayerBuilder::fatal_target`](crate::BunyanFormattingLayerBuilder::fatal_target).
+///
+/// ```rust
+/// use tracing_bunyan_formatter::{BunyanFormattingLayer, LevelMapping};
+///
+/// let formatting_layer = BunyanFormattingLayer::builder("tracing_example")
+///     // Every error terminates the process in this application.
+///     .level_mapping(LevelMapping {
+///         error: LevelMapping::FATAL,
+///         ..LevelMapping::default()
+///     })
+///     .build()
+///     .expect("Invalid formatting layer configuration");
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LevelMapping {
+    pub trace: u16,
+    pub debug: u16,
+    pub info: u16,
+    pub warn: u16,
+    pub error: u16,
+}
+
+impl LevelMapping {
+    /// Bunyan's FATAL level.
+    pub const FATAL: u16 = 60;
+
+    /// The Bunyan level of `level`.
+    pub(crate) fn bunyan_level(&self, level: &Level) -> u16 {
+        match *level {
+            Level::ERROR => self.error,
+            Level::WARN => self.warn,
+            Level::INFO => self.info,
+            Level::DEBUG => self.debug,
+            Level::TRACE => self.trace,
+        }
+    }
+
+    /// The name of a record level: the name of its `tracing` level (e.g. `WARN`), or `FATAL` if
+    /// its Bunyan level is FATAL.
+    pub(crate) fn level_name(level: &Level, bunyan_level: u16) -> &'static str {
+        if bunyan_level == Self::FATAL {
+            return "FATAL";
+        }
+        match *level {
+            Level::ERROR => "ERROR",
+            Level::WARN => "WARN",
+            Level::INFO => "INFO",
+            Level::DEBUG => "DEBUG",
+            Level::TRACE => "TRACE",
+        }
+    }
+}
+
+impl Default for LevelMapping {
+    fn default() -> Self {
+        Self {
+            trace: 10,
+            debug: 20,
+            info: 30,
+            warn: 40,
+            error: 50,
+        }
+    }
+}
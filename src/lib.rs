@@ -1,8 +1,35 @@
 #![allow(clippy::needless_doctest_main)]
 #![doc = include_str!("../README.md")]
 
+mod builder;
+mod config_handle;
+mod error_handler;
+mod fields_provider;
 mod formatting_layer;
+mod glob;
+mod level_mapping;
+mod message_formatter;
+mod non_blocking;
+#[cfg(feature = "redaction")]
+mod redaction;
+mod skip_rule;
+mod span_events;
+mod stats;
 mod storage_layer;
+mod timer;
 
+pub use builder::*;
+pub use config_handle::BunyanConfigHandle;
+pub use error_handler::*;
+pub use fields_provider::*;
 pub use formatting_layer::*;
+pub use level_mapping::*;
+pub use message_formatter::*;
+pub use non_blocking::*;
+#[cfg(feature = "redaction")]
+pub use redaction::*;
+pub use skip_rule::SkipRule;
+pub use span_events::*;
+pub use stats::*;
 pub use storage_layer::*;
+pub use timer::*;
@@ -0,0 +1,127 @@
+use crate::formatting_layer::Type;
+use crate::storage_layer::JsonStorage;
+
+/// A `MessageFormatter` provides the `msg` field of span records (START, END, ENTER, EXIT)
+/// and of events.
+///
+/// The default formatter prefixes records with the uppercase name of their span, e.g.
+/// `[AN_INTERESTING_SPAN - START]` or `[AN_INTERESTING_SPAN - EVENT] My event message`.
+/// It is ignored if [`BunyanFormattingLayer::serialize_span_type`](crate::BunyanFormattingLayer::serialize_span_type)
+/// is enabled.
+///
+/// ```rust
+/// use tracing_bunyan_formatter::{BunyanFormattingLayer, MessageFormatter, SpanInfo, Type};
+///
+/// /// Prefix records with a breadcrumb of all their spans, e.g. `[request > db_query - START]`.
+/// struct Breadcrumb;
+///
+/// impl MessageFormatter for Breadcrumb {
+///     fn format_span(&self, span: &SpanInfo<'_>, ty: Type) -> String {
+///         format!("[{} - {}]", span.names_from_root().join(" > "), ty)
+///     }
+/// }
+///
+/// let formatting_layer = BunyanFormattingLayer::builder("test")
+///     .message_formatter(Breadcrumb)
+///     .build()
+///     .unwrap();
+/// ```
+pub trait MessageFormatter: Send + Sync + 'static {
+    /// The `msg` of a span record. Defaults to `[SPAN_NAME - TYPE]`.
+    fn format_span(&self, span: &SpanInfo<'_>, ty: Type) -> String {
+        format!("[{} - {}]", span.name().to_uppercase(), ty)
+    }
+
+    /// The `msg` of an event, given its `message` and its `fields`. `span` is the span the
+    /// event is in, if any.
+    ///
+    /// Defaults to `message` prefixed with [`MessageFormatter::format_span`] for
+    /// [`Type::Event`], if the event is in a span.
+    #[allow(unused_variables)]
+    fn format_event(
+        &self,
+        message: &str,
+        span: Option<&SpanInfo<'_>>,
+        fields: &JsonStorage<'_>,
+    ) -> String {
+        match span {
+            Some(span) => format!("{} {}", self.format_span(span, Type::Event), message),
+            None => message.to_owned(),
+        }
+    }
+}
+
+impl Default for Box<dyn MessageFormatter> {
+    fn default() -> Self {
+        Box::new(DefaultMessageFormatter)
+    }
+}
+
+/// The default [`MessageFormatter`], e.g. `[AN_INTERESTING_SPAN - EVENT] My event message`.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct DefaultMessageFormatter;
+
+impl MessageFormatter for DefaultMessageFormatter {}
+
+/// A [`MessageFormatter`] without span context: span records use the span name and events
+/// their message as is.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PlainMessageFormatter;
+
+impl MessageFormatter for PlainMessageFormatter {
+    fn format_span(&self, span: &SpanInfo<'_>, _ty: Type) -> String {
+        span.name().to_owned()
+    }
+
+    fn format_event(
+        &self,
+        message: &str,
+        _span: Option<&SpanInfo<'_>>,
+        _fields: &JsonStorage<'_>,
+    ) -> String {
+        message.to_owned()
+    }
+}
+
+/// What to use as the message of events without a `message` field,
+/// e.g. `info!(user_id = 3)`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum MessageFallback {
+    /// The target of the event, e.g. `my_crate::handlers`.
+    #[default]
+    Target,
+    /// The name of the event, e.g. `event src/handlers.rs:42`.
+    MetadataName,
+    /// The fields of the event rendered as `key=value`, sorted by key, e.g. `user_id=3`.
+    ///
+    /// Skipped fields are left out.
+    Fields,
+    /// An empty message.
+    Empty,
+}
+
+/// The span a record is about, as seen by a [`MessageFormatter`].
+pub struct SpanInfo<'a> {
+    pub(crate) name: &'static str,
+    pub(crate) fields: Option<&'a JsonStorage<'static>>,
+    pub(crate) names_from_root: &'a dyn Fn() -> Vec<&'static str>,
+}
+
+impl<'a> SpanInfo<'a> {
+    /// The name of the span.
+    pub fn name(&self) -> &'static str {
+        self.name
+    }
+
+    /// The fields of the span, if they are stored by `JsonStorageLayer` or by
+    /// [`BunyanFormattingLayerBuilder::store_span_fields`](crate::BunyanFormattingLayerBuilder::store_span_fields).
+    pub fn fields(&self) -> Option<&'a JsonStorage<'static>> {
+        self.fields
+    }
+
+    /// The names of the span and of all its ancestors, from the root span to this one.
+    pub fn names_from_root(&self) -> Vec<&'static str> {
+        (self.names_from_root)()
+    }
+}
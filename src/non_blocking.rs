@@ -0,0 +1,325 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Condvar, Mutex, MutexGuard};
+use std::thread::JoinHandle;
+use tracing_subscriber::fmt::MakeWriter;
+
+/// The default number of records [`NonBlocking`] can buffer before applying its
+/// [`OverflowPolicy`].
+pub const DEFAULT_BUFFERED_RECORDS_LIMIT: usize = 128_000;
+
+/// What [`NonBlocking`] does with a record when its queue is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum OverflowPolicy {
+    /// Wait for the worker thread to make room for the record.
+    #[default]
+    Block,
+    /// Drop the record.
+    DropNewest,
+    /// Drop the oldest record in the queue to make room for the record.
+    DropOldest,
+}
+
+/// Create a [`NonBlocking`] writer with the default configuration, writing to `writer` from
+/// a dedicated thread.
+///
+/// The returned [`WorkerGuard`] must be kept alive as long as records are written: see
+/// [`NonBlockingBuilder`] for an example.
+pub fn non_blocking<T: Write + Send + 'static>(writer: T) -> (NonBlocking, WorkerGuard) {
+    NonBlockingBuilder::default().finish(writer)
+}
+
+/// A `MakeWriter` pushing records onto a bounded queue, to be written by a dedicated worker
+/// thread, so that formatting layers don't block on slow writers (e.g. a stdout pipe).
+///
+/// Each call to `write` is treated as a record: `BunyanFormattingLayer` writes each record
+/// with a single call.
+#[derive(Clone, Debug)]
+pub struct NonBlocking {
+    queue: Arc<Queue>,
+    overflow_policy: OverflowPolicy,
+}
+
+impl NonBlocking {
+    /// The number of records dropped so far, because the queue was full or because the worker
+    /// thread stopped: the [`WorkerGuard`] was dropped or the underlying writer panicked.
+    pub fn dropped_records(&self) -> u64 {
+        self.queue.dropped.load(Ordering::Relaxed)
+    }
+
+    /// The number of records the underlying writer failed to write (or flush) so far.
+    /// See [`NonBlockingBuilder::write_error_handler`] to be notified of each failure.
+    pub fn write_errors(&self) -> u64 {
+        self.queue.write_errors.load(Ordering::Relaxed)
+    }
+
+    fn push(&self, record: Vec<u8>) {
+        let mut state = self.queue.lock();
+        while state.records.len() >= self.queue.capacity && !state.shutdown {
+            match self.overflow_policy {
+                OverflowPolicy::Block => {
+                    state = self
+                        .queue
+                        .not_full
+                        .wait(state)
+                        .unwrap_or_else(|e| e.into_inner());
+                }
+                OverflowPolicy::DropNewest => {
+                    self.queue.dropped.fetch_add(1, Ordering::Relaxed);
+                    return;
+                }
+                OverflowPolicy::DropOldest => {
+                    state.records.pop_front();
+                    self.queue.dropped.fetch_add(1, Ordering::Relaxed);
+                }
+            }
+        }
+        if state.shutdown {
+            // The worker thread is gone, or about to be: nobody would write this record.
+            self.queue.dropped.fetch_add(1, Ordering::Relaxed);
+            return;
+        }
+        state.records.push_back(record);
+        self.queue.not_empty.notify_one();
+    }
+}
+
+impl Write for NonBlocking {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.push(buf.to_vec());
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl<'a> MakeWriter<'a> for NonBlocking {
+    type Writer = NonBlocking;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        self.clone()
+    }
+}
+
+/// Configure a [`NonBlocking`] writer.
+///
+/// ```rust
+/// use tracing_bunyan_formatter::{
+///     BunyanFormattingLayer, JsonStorageLayer, NonBlockingBuilder, OverflowPolicy,
+/// };
+/// use tracing_subscriber::layer::SubscriberExt;
+/// use tracing_subscriber::Registry;
+///
+/// let (writer, _guard) = NonBlockingBuilder::default()
+///     .buffered_records_limit(10_000)
+///     // Losing the oldest records is better than stalling request handlers.
+///     .overflow_policy(OverflowPolicy::DropOldest)
+///     .finish(std::io::stdout());
+/// let formatting_layer = BunyanFormattingLayer::new("tracing_example".into(), writer);
+/// let subscriber = Registry::default()
+///     .with(JsonStorageLayer)
+///     .with(formatting_layer);
+/// // `_guard` flushes the buffered records when dropped, e.g. at the end of `main`.
+/// ```
+#[derive(Clone)]
+pub struct NonBlockingBuilder {
+    buffered_records_limit: usize,
+    overflow_policy: OverflowPolicy,
+    thread_name: String,
+    write_error_handler: Option<Arc<WriteErrorHandler>>,
+}
+
+type WriteErrorHandler = dyn Fn(&io::Error) + Send + Sync;
+
+impl fmt::Debug for NonBlockingBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("NonBlockingBuilder")
+            .field("buffered_records_limit", &self.buffered_records_limit)
+            .field("overflow_policy", &self.overflow_policy)
+            .field("thread_name", &self.thread_name)
+            .field("write_error_handler", &self.write_error_handler.is_some())
+            .finish()
+    }
+}
+
+impl Default for NonBlockingBuilder {
+    fn default() -> Self {
+        Self {
+            buffered_records_limit: DEFAULT_BUFFERED_RECORDS_LIMIT,
+            overflow_policy: OverflowPolicy::default(),
+            thread_name: "tracing-bunyan-formatter-worker".to_owned(),
+            write_error_handler: None,
+        }
+    }
+}
+
+impl NonBlockingBuilder {
+    /// The maximum number of records waiting to be written. Defaults to
+    /// [`DEFAULT_BUFFERED_RECORDS_LIMIT`].
+    pub fn buffered_records_limit(mut self, limit: usize) -> Self {
+        self.buffered_records_limit = limit.max(1);
+        self
+    }
+
+    /// What to do with records when the queue is full. Defaults to [`OverflowPolicy::Block`].
+    pub fn overflow_policy(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow_policy = policy;
+        self
+    }
+
+    /// The name of the worker thread.
+    pub fn thread_name(mut self, name: impl Into<String>) -> Self {
+        self.thread_name = name.into();
+        self
+    }
+
+    /// Call `handler` from the worker thread when the underlying writer fails to write
+    /// (or flush) records, e.g. to report them on stderr.
+    ///
+    /// Failed writes are counted in any case, see [`NonBlocking::write_errors`].
+    pub fn write_error_handler(
+        mut self,
+        handler: impl Fn(&io::Error) + Send + Sync + 'static,
+    ) -> Self {
+        self.write_error_handler = Some(Arc::new(handler));
+        self
+    }
+
+    /// Spawn the worker thread writing to `writer`.
+    ///
+    /// # Panics
+    ///
+    /// If the worker thread cannot be spawned.
+    pub fn finish<T: Write + Send + 'static>(self, writer: T) -> (NonBlocking, WorkerGuard) {
+        let queue = Arc::new(Queue {
+            state: Mutex::new(QueueState::default()),
+            not_empty: Condvar::new(),
+            not_full: Condvar::new(),
+            capacity: self.buffered_records_limit,
+            dropped: AtomicU64::new(0),
+            write_errors: AtomicU64::new(0),
+        });
+        let worker = std::thread::Builder::new()
+            .name(self.thread_name)
+            .spawn({
+                let queue = queue.clone();
+                let write_error_handler = self.write_error_handler;
+                move || {
+                    let _stop = StopOnExit(&queue);
+                    queue.work(writer, write_error_handler.as_deref())
+                }
+            })
+            .expect("Failed to spawn the non-blocking writer thread");
+        let non_blocking = NonBlocking {
+            queue: queue.clone(),
+            overflow_policy: self.overflow_policy,
+        };
+        let guard = WorkerGuard {
+            queue,
+            worker: Some(worker),
+        };
+        (non_blocking, guard)
+    }
+}
+
+/// Stops the worker thread of a [`NonBlocking`] writer when dropped, once all the buffered
+/// records have been written and the underlying writer flushed.
+///
+/// Records written after the guard is dropped are discarded.
+#[must_use = "Dropping the guard stops the worker thread: records would be discarded"]
+#[derive(Debug)]
+pub struct WorkerGuard {
+    queue: Arc<Queue>,
+    worker: Option<JoinHandle<()>>,
+}
+
+impl Drop for WorkerGuard {
+    fn drop(&mut self) {
+        self.queue.lock().shutdown = true;
+        self.queue.not_empty.notify_all();
+        self.queue.not_full.notify_all();
+        if let Some(worker) = self.worker.take() {
+            let _ = worker.join();
+        }
+    }
+}
+
+#[derive(Debug)]
+struct Queue {
+    state: Mutex<QueueState>,
+    not_empty: Condvar,
+    not_full: Condvar,
+    capacity: usize,
+    dropped: AtomicU64,
+    write_errors: AtomicU64,
+}
+
+#[derive(Debug, Default)]
+struct QueueState {
+    records: VecDeque<Vec<u8>>,
+    shutdown: bool,
+}
+
+impl Queue {
+    // A panicking writer must not prevent other threads from logging: see `StopOnExit`.
+    fn lock(&self) -> MutexGuard<'_, QueueState> {
+        self.state.lock().unwrap_or_else(|e| e.into_inner())
+    }
+
+    /// Write records as they come, until the queue is shut down and empty.
+    fn work<T: Write>(&self, mut writer: T, write_error_handler: Option<&WriteErrorHandler>) {
+        let write_error = |e: io::Error| {
+            self.write_errors.fetch_add(1, Ordering::Relaxed);
+            if let Some(handler) = write_error_handler {
+                handler(&e);
+            }
+        };
+        loop {
+            let records = {
+                let mut state = self.lock();
+                while state.records.is_empty() && !state.shutdown {
+                    state = self
+                        .not_empty
+                        .wait(state)
+                        .unwrap_or_else(|e| e.into_inner());
+                }
+                if state.records.is_empty() {
+                    break;
+                }
+                std::mem::take(&mut state.records)
+            };
+            self.not_full.notify_all();
+            for record in records {
+                if let Err(e) = writer.write_all(&record) {
+                    write_error(e);
+                }
+            }
+            if let Err(e) = writer.flush() {
+                write_error(e);
+            }
+        }
+    }
+}
+
+/// Shut the queue down when the worker thread exits, even if the writer panicked: logging
+/// threads would otherwise wait forever for room in the queue with [`OverflowPolicy::Block`].
+struct StopOnExit<'a>(&'a Queue);
+
+impl Drop for StopOnExit<'_> {
+    fn drop(&mut self) {
+        let mut state = self.0.lock();
+        state.shutdown = true;
+        // Nobody is left to write the records still queued.
+        let lost = state.records.len() as u64;
+        state.records.clear();
+        self.0.dropped.fetch_add(lost, Ordering::Relaxed);
+        drop(state);
+        self.0.not_empty.notify_all();
+        self.0.not_full.notify_all();
+    }
+}
@@ -0,0 +1,226 @@
+use crate::glob::glob_match;
+use regex::Regex;
+use serde_json::Value;
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
+
+/// A regular expression matching bearer tokens (e.g. `Bearer eyJhbGciOi...`), to be used with
+/// [`BunyanFormattingLayerBuilder::redact_values`](crate::BunyanFormattingLayerBuilder::redact_values).
+pub const BEARER_TOKEN_PATTERN: &str = r"(?i)\bbearer\s+[a-z0-9\-._~+/]+=*";
+
+/// A regular expression matching email addresses, to be used with
+/// [`BunyanFormattingLayerBuilder::redact_values`](crate::BunyanFormattingLayerBuilder::redact_values).
+pub const EMAIL_PATTERN: &str = r"[a-zA-Z0-9._%+\-]+@[a-zA-Z0-9.\-]+\.[a-zA-Z]{2,}";
+
+/// Which field names a redaction rule applies to.
+///
+/// Patterns are validated when building the layer, see
+/// [`BunyanFormattingLayerBuilder::build`](crate::BunyanFormattingLayerBuilder::build).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum FieldPattern {
+    /// Match a field name exactly, e.g. `password`.
+    Exact(String),
+    /// Match field names against a glob, where `*` matches any sequence of characters
+    /// and `?` a single one, e.g. `*_token`.
+    Glob(String),
+    /// Match field names against a regular expression, e.g. `(?i)secret`.
+    Regex(String),
+}
+
+impl FieldPattern {
+    /// See [`FieldPattern::Exact`].
+    pub fn exact(name: impl Into<String>) -> Self {
+        Self::Exact(name.into())
+    }
+
+    /// See [`FieldPattern::Glob`].
+    pub fn glob(pattern: impl Into<String>) -> Self {
+        Self::Glob(pattern.into())
+    }
+
+    /// See [`FieldPattern::Regex`].
+    pub fn regex(pattern: impl Into<String>) -> Self {
+        Self::Regex(pattern.into())
+    }
+}
+
+/// How a sensitive value is replaced.
+#[derive(Clone)]
+#[non_exhaustive]
+pub enum Redaction {
+    /// Replace the value with `"[REDACTED]"`.
+    Replace,
+    /// Replace the value with a hash of it (e.g. `"[HASH:a94a8fe5ccb19ba6]"`), to correlate
+    /// records without revealing the value.
+    ///
+    /// This is a 64-bit FNV-1a hash: it is stable across runs and platforms, but it is not
+    /// cryptographically secure. Use [`Redaction::Custom`] for a keyed hash.
+    Hash,
+    /// Replace all the characters but the last `visible` ones with `*`
+    /// (e.g. `"************1234"`). Values that aren't longer than `visible` are masked entirely.
+    Mask { visible: usize },
+    /// Replace the value with the output of a custom function.
+    Custom(Arc<dyn Fn(&str) -> String + Send + Sync>),
+}
+
+impl fmt::Debug for Redaction {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Redaction::Replace => write!(f, "Replace"),
+            Redaction::Hash => write!(f, "Hash"),
+            Redaction::Mask { visible } => {
+                f.debug_struct("Mask").field("visible", visible).finish()
+            }
+            Redaction::Custom(_) => write!(f, "Custom(..)"),
+        }
+    }
+}
+
+impl Redaction {
+    fn apply(&self, value: &str) -> String {
+        match self {
+            Redaction::Replace => "[REDACTED]".to_owned(),
+            Redaction::Hash => format!("[HASH:{:016x}]", fnv1a(value.as_bytes())),
+            Redaction::Mask { visible } => {
+                let length = value.chars().count();
+                let hidden = if length > *visible {
+                    length - visible
+                } else {
+                    length
+                };
+                value
+                    .chars()
+                    .enumerate()
+                    .map(|(i, c)| if i < hidden { '*' } else { c })
+                    .collect()
+            }
+            Redaction::Custom(redact) => redact(value),
+        }
+    }
+}
+
+/// 64-bit FNV-1a.
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf29ce484222325, |hash, byte| {
+        (hash ^ u64::from(*byte)).wrapping_mul(0x100000001b3)
+    })
+}
+
+/// A field name matcher, compiled from a [`FieldPattern`].
+#[derive(Clone, Debug)]
+enum FieldMatcher {
+    Exact(String),
+    Glob(String),
+    Regex(Regex),
+}
+
+impl FieldMatcher {
+    fn matches(&self, key: &str) -> bool {
+        match self {
+            FieldMatcher::Exact(name) => name == key,
+            FieldMatcher::Glob(pattern) => glob_match(pattern, key),
+            FieldMatcher::Regex(regex) => regex.is_match(key),
+        }
+    }
+}
+
+/// The compiled set of redaction rules of a `BunyanFormattingLayer`.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Redactor {
+    fields: Vec<(FieldMatcher, Redaction)>,
+    values: Vec<(Regex, Redaction)>,
+}
+
+impl Redactor {
+    /// Add a rule redacting whole fields, by name.
+    pub(crate) fn add_field_rule(
+        &mut self,
+        pattern: FieldPattern,
+        redaction: Redaction,
+    ) -> Result<(), (String, regex::Error)> {
+        let matcher = match pattern {
+            FieldPattern::Exact(name) => FieldMatcher::Exact(name),
+            FieldPattern::Glob(pattern) => FieldMatcher::Glob(pattern),
+            FieldPattern::Regex(pattern) => {
+                FieldMatcher::Regex(Regex::new(&pattern).map_err(|e| (pattern, e))?)
+            }
+        };
+        self.fields.push((matcher, redaction));
+        Ok(())
+    }
+
+    /// Add a rule redacting the parts of string values matching a regular expression.
+    pub(crate) fn add_value_rule(
+        &mut self,
+        pattern: String,
+        redaction: Redaction,
+    ) -> Result<(), (String, regex::Error)> {
+        let regex = Regex::new(&pattern).map_err(|e| (pattern, e))?;
+        self.values.push((regex, redaction));
+        Ok(())
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.fields.is_empty() && self.values.is_empty()
+    }
+
+    /// Redact the value of the field `key`, including the values nested in it.
+    pub(crate) fn redact(&self, key: &str, value: Value) -> Value {
+        match self.redact_field(key, &value) {
+            Some(redacted) => Value::String(redacted),
+            None => self.redact_value(value),
+        }
+    }
+
+    /// Redact the whole value of the field `key` if a field rule matches its name.
+    /// Value rules are not applied.
+    pub(crate) fn redact_field(&self, key: &str, value: &Value) -> Option<String> {
+        let (_, redaction) = self.fields.iter().find(|(m, _)| m.matches(key))?;
+        Some(match value {
+            Value::String(s) => redaction.apply(s),
+            other => redaction.apply(&other.to_string()),
+        })
+    }
+
+    /// Redact the parts of a value, and of the values nested in it, matching the rules.
+    fn redact_value(&self, value: Value) -> Value {
+        match value {
+            Value::String(s) => match self.redact_str(&s) {
+                Cow::Borrowed(_) => Value::String(s),
+                Cow::Owned(redacted) => Value::String(redacted),
+            },
+            Value::Array(values) => Value::Array(
+                values
+                    .into_iter()
+                    .map(|value| self.redact_value(value))
+                    .collect(),
+            ),
+            Value::Object(values) => Value::Object(
+                values
+                    .into_iter()
+                    .map(|(key, value)| {
+                        let value = self.redact(&key, value);
+                        (key, value)
+                    })
+                    .collect(),
+            ),
+            other => other,
+        }
+    }
+
+    /// Redact the parts of a string matching any of the value rules.
+    pub(crate) fn redact_str<'s>(&self, value: &'s str) -> Cow<'s, str> {
+        let mut value = Cow::Borrowed(value);
+        for (regex, redaction) in &self.values {
+            if let Cow::Owned(redacted) = regex
+                .replace_all(&value, |captures: &regex::Captures<'_>| {
+                    redaction.apply(&captures[0])
+                })
+            {
+                value = Cow::Owned(redacted);
+            }
+        }
+        value
+    }
+}
@@ -0,0 +1,179 @@
+use crate::formatting_layer::BUNYAN_REQUIRED_FIELDS;
+use crate::glob::glob_match;
+use ahash::HashSet;
+
+/// A rule to skip fields, more flexible than the plain field names accepted by
+/// [`BunyanFormattingLayer::skip_fields`](crate::BunyanFormattingLayer::skip_fields).
+///
+/// Field names, targets and span names are matched as globs, where `*` matches any sequence
+/// of characters and `?` a single one.
+///
+/// ```rust
+/// use tracing_bunyan_formatter::{BunyanFormattingLayer, SkipRule};
+///
+/// let formatting_layer = BunyanFormattingLayer::new("test".into(), std::io::stdout)
+///     // Skip `internal.*` fields everywhere
+///     .skip_rule(SkipRule::new("internal.*"))
+///     // Skip `body` for records emitted by hyper
+///     .and_then(|layer| layer.skip_rule(SkipRule::new("body").for_target("hyper::*")))
+///     // Skip `payload` inside `upload` spans
+///     .and_then(|layer| layer.skip_rule(SkipRule::new("payload").in_span("upload")))
+///     .expect("One of the specified fields cannot be skipped");
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SkipRule {
+    field: String,
+    targets: Vec<String>,
+    spans: Vec<String>,
+}
+
+impl SkipRule {
+    /// Skip the fields whose name matches `field`, e.g. `body` or `internal.*`.
+    pub fn new(field: impl Into<String>) -> Self {
+        Self {
+            field: field.into(),
+            targets: Vec::new(),
+            spans: Vec::new(),
+        }
+    }
+
+    /// Only skip fields for records whose target matches `target`, e.g. `hyper::*`.
+    ///
+    /// If called multiple times, records matching any of the targets are affected.
+    pub fn for_target(mut self, target: impl Into<String>) -> Self {
+        self.targets.push(target.into());
+        self
+    }
+
+    /// Only skip fields for records emitted in a span whose name matches `span`,
+    /// either directly or in one of its children. Span records (START, END) are affected
+    /// by rules matching the span itself.
+    ///
+    /// If called multiple times, records matching any of the span names are affected.
+    pub fn in_span(mut self, span: impl Into<String>) -> Self {
+        self.spans.push(span.into());
+        self
+    }
+
+    /// Whether the rule could skip the field `key`.
+    fn matches_field(&self, key: &str) -> bool {
+        glob_match(&self.field, key)
+    }
+
+    /// Whether the rule is scoped to some spans, i.e. whether it needs to know the span names
+    /// of a record to decide whether it applies.
+    fn is_span_scoped(&self) -> bool {
+        !self.spans.is_empty()
+    }
+
+    /// Whether the rule applies to a record with the given target, in the given spans.
+    fn applies_to(&self, target: &str, span_names: &[&str]) -> bool {
+        let target_matches =
+            self.targets.is_empty() || self.targets.iter().any(|t| glob_match(t, target));
+        let span_matches = self.spans.is_empty()
+            || self
+                .spans
+                .iter()
+                .any(|s| span_names.iter().any(|name| glob_match(s, name)));
+        target_matches && span_matches
+    }
+}
+
+/// The fields skipped by a layer: plain field names and globs, plus scoped skip rules.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SkipList {
+    names: HashSet<String>,
+    globs: Vec<SkipRule>,
+    rules: Vec<SkipRule>,
+}
+
+impl SkipList {
+    /// Skip a field, whose name can be a glob.
+    ///
+    /// It gives the name back if it could skip a required core Bunyan field.
+    pub(crate) fn add_field(&mut self, field: String) -> Result<(), String> {
+        if field.contains(['*', '?']) {
+            let glob = SkipRule::new(field);
+            check_rule(&glob)?;
+            self.globs.push(glob);
+            Ok(())
+        } else if BUNYAN_REQUIRED_FIELDS.contains(&field.as_str()) {
+            Err(field)
+        } else {
+            self.names.insert(field);
+            Ok(())
+        }
+    }
+
+    /// Add a skip rule.
+    ///
+    /// It gives the field pattern back if the rule could skip a required core Bunyan field.
+    pub(crate) fn add_rule(&mut self, rule: SkipRule) -> Result<(), String> {
+        check_rule(&rule)?;
+        self.rules.push(rule);
+        Ok(())
+    }
+
+    /// Replace the skipped field names and globs with the ones of `other`, keeping the rules.
+    pub(crate) fn replace_fields(&mut self, other: &SkipList) {
+        self.names = other.names.clone();
+        self.globs = other.globs.clone();
+    }
+
+    /// Replace the skip rules with the ones of `other`, keeping the field names and globs.
+    pub(crate) fn replace_rules(&mut self, other: &SkipList) {
+        self.rules = other.rules.clone();
+    }
+
+    /// Resolve the fields to skip for a record with the given target.
+    ///
+    /// `span_names` lists the names of the spans the record is in: it's only called if some
+    /// skip rules are scoped to spans.
+    pub(crate) fn for_record(
+        &self,
+        target: &str,
+        span_names: impl FnOnce() -> Vec<&'static str>,
+    ) -> SkippedFields<'_> {
+        let span_names = if self.rules.iter().any(SkipRule::is_span_scoped) {
+            span_names()
+        } else {
+            Vec::new()
+        };
+        SkippedFields {
+            names: &self.names,
+            rules: self
+                .globs
+                .iter()
+                .chain(
+                    self.rules
+                        .iter()
+                        .filter(|rule| rule.applies_to(target, &span_names)),
+                )
+                .collect(),
+        }
+    }
+}
+
+/// Give the field pattern of `rule` back if it could skip a required core Bunyan field.
+fn check_rule(rule: &SkipRule) -> Result<(), String> {
+    if BUNYAN_REQUIRED_FIELDS
+        .iter()
+        .any(|field| rule.matches_field(field))
+    {
+        return Err(rule.field.clone());
+    }
+    Ok(())
+}
+
+/// The fields skipped for the record being formatted: the plain skipped field names, plus the
+/// skip rules applying to it.
+pub(crate) struct SkippedFields<'a> {
+    names: &'a HashSet<String>,
+    rules: Vec<&'a SkipRule>,
+}
+
+impl SkippedFields<'_> {
+    pub(crate) fn contains(&self, key: &str) -> bool {
+        self.names.contains(key) || self.rules.iter().any(|rule| rule.matches_field(key))
+    }
+}
@@ -0,0 +1,79 @@
+use std::fmt;
+use std::ops::BitOr;
+
+/// Which span lifecycle records `BunyanFormattingLayer` emits, similar to
+/// `tracing_subscriber::fmt::format::FmtSpan`.
+///
+/// Policies can be combined with `|`:
+///
+/// ```rust
+/// use tracing_bunyan_formatter::{BunyanFormattingLayer, SpanEvents};
+///
+/// let formatting_layer = BunyanFormattingLayer::builder("tracing_example")
+///     // Only END records, which carry `elapsed_milliseconds`...
+///     .span_events(SpanEvents::END)
+///     // ...no span records at all for hyper...
+///     .target_span_events("hyper::*", SpanEvents::NONE)
+///     // ...and every lifecycle record for our own request spans.
+///     .target_span_events("my_app::http", SpanEvents::START | SpanEvents::END | SpanEvents::ENTER)
+///     .build()
+///     .expect("Invalid formatting layer configuration");
+/// ```
+///
+/// The default is `SpanEvents::START | SpanEvents::END`.
+#[derive(Clone, Copy, PartialEq, Eq, Hash)]
+pub struct SpanEvents(u8);
+
+impl SpanEvents {
+    /// No span records.
+    pub const NONE: SpanEvents = SpanEvents(0);
+    /// A START record when a span is created.
+    pub const START: SpanEvents = SpanEvents(1 << 0);
+    /// An END record when a span is closed.
+    pub const END: SpanEvents = SpanEvents(1 << 1);
+    /// An ENTER record every time a span is entered.
+    pub const ENTER: SpanEvents = SpanEvents(1 << 2);
+    /// An EXIT record every time a span is exited.
+    pub const EXIT: SpanEvents = SpanEvents(1 << 3);
+    /// All span records.
+    pub const ALL: SpanEvents = SpanEvents(0b1111);
+
+    /// Whether all the records of `other` are emitted by this policy.
+    pub(crate) fn contains(self, other: SpanEvents) -> bool {
+        self.0 & other.0 == other.0
+    }
+}
+
+impl Default for SpanEvents {
+    fn default() -> Self {
+        SpanEvents::START | SpanEvents::END
+    }
+}
+
+impl BitOr for SpanEvents {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self {
+        SpanEvents(self.0 | rhs.0)
+    }
+}
+
+impl fmt::Debug for SpanEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names: Vec<_> = [
+            (SpanEvents::START, "START"),
+            (SpanEvents::END, "END"),
+            (SpanEvents::ENTER, "ENTER"),
+            (SpanEvents::EXIT, "EXIT"),
+        ]
+        .iter()
+        .filter(|(events, _)| self.contains(*events))
+        .map(|(_, name)| *name)
+        .collect();
+        if names.is_empty() {
+            write!(f, "SpanEvents(NONE)")
+        } else {
+            write!(f, "SpanEvents({})", names.join(" | "))
+        }
+    }
+}
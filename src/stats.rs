@@ -0,0 +1,187 @@
+use crate::formatting_layer::Type;
+use crate::level_mapping::LevelMapping;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use tracing_core::metadata::Level;
+
+/// The counters of a `BunyanFormattingLayer`, updated as records are emitted.
+#[derive(Debug, Default)]
+pub(crate) struct Stats {
+    records_by_level: [AtomicU64; 6],
+    records_by_type: [AtomicU64; 5],
+    bytes_written: AtomicU64,
+    serialization_errors: AtomicU64,
+    write_errors: AtomicU64,
+    dropped_records: AtomicU64,
+}
+
+impl Stats {
+    /// A record of type `ty` and with the given level was written, to the primary writer or to
+    /// the fallback one.
+    pub(crate) fn record_written(&self, level: &Level, bunyan_level: u16, ty: Type, bytes: usize) {
+        let level = if bunyan_level == LevelMapping::FATAL {
+            5
+        } else {
+            match *level {
+                Level::TRACE => 0,
+                Level::DEBUG => 1,
+                Level::INFO => 2,
+                Level::WARN => 3,
+                Level::ERROR => 4,
+            }
+        };
+        let ty = match ty {
+            Type::EnterSpan => 0,
+            Type::ExitSpan => 1,
+            Type::SpanEntered => 2,
+            Type::SpanExited => 3,
+            Type::Event => 4,
+        };
+        self.records_by_level[level].fetch_add(1, Ordering::Relaxed);
+        self.records_by_type[ty].fetch_add(1, Ordering::Relaxed);
+        self.bytes_written
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn serialization_error(&self) {
+        self.serialization_errors.fetch_add(1, Ordering::Relaxed);
+        self.dropped_records.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn write_error(&self) {
+        self.write_errors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// A formatted record could be written neither to the primary writer nor to the fallback one.
+    pub(crate) fn record_dropped(&self) {
+        self.dropped_records.fetch_add(1, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> BunyanStats {
+        let load = |counter: &AtomicU64| counter.load(Ordering::Relaxed);
+        let [trace, debug, info, warn, error, fatal] = &self.records_by_level;
+        let [start, end, enter, exit, event] = &self.records_by_type;
+        BunyanStats {
+            records_by_level: RecordsByLevel {
+                trace: load(trace),
+                debug: load(debug),
+                info: load(info),
+                warn: load(warn),
+                error: load(error),
+                fatal: load(fatal),
+            },
+            records_by_type: RecordsByType {
+                start: load(start),
+                end: load(end),
+                enter: load(enter),
+                exit: load(exit),
+                event: load(event),
+            },
+            bytes_written: load(&self.bytes_written),
+            serialization_errors: load(&self.serialization_errors),
+            write_errors: load(&self.write_errors),
+            dropped_records: load(&self.dropped_records),
+        }
+    }
+}
+
+/// A handle to read the counters of a `BunyanFormattingLayer`, obtained via
+/// [`BunyanFormattingLayer::stats_handle`](crate::BunyanFormattingLayer::stats_handle).
+///
+/// Handles are cheap to clone: export their [snapshots](BunyanStatsHandle::snapshot) to your
+/// metrics pipeline periodically.
+///
+/// ```rust
+/// use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
+/// use tracing_subscriber::layer::SubscriberExt;
+/// use tracing_subscriber::Registry;
+///
+/// let formatting_layer = BunyanFormattingLayer::new("tracing_example".into(), std::io::stdout);
+/// let stats = formatting_layer.stats_handle();
+/// let subscriber = Registry::default()
+///     .with(JsonStorageLayer)
+///     .with(formatting_layer);
+/// tracing::subscriber::with_default(subscriber, || {
+///     tracing::info!("Hello");
+/// });
+///
+/// let snapshot = stats.snapshot();
+/// assert_eq!(snapshot.records_by_level.info, 1);
+/// assert_eq!(snapshot.records_written(), 1);
+/// ```
+#[derive(Clone, Debug)]
+pub struct BunyanStatsHandle {
+    pub(crate) stats: Arc<Stats>,
+}
+
+impl BunyanStatsHandle {
+    /// The current value of the counters.
+    ///
+    /// Counters are read one at a time, while records may still be emitted: a snapshot is not
+    /// guaranteed to be consistent across counters.
+    pub fn snapshot(&self) -> BunyanStats {
+        self.stats.snapshot()
+    }
+}
+
+/// The counters of a `BunyanFormattingLayer` at a given time, since it was created.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct BunyanStats {
+    /// The records written, by level.
+    pub records_by_level: RecordsByLevel,
+    /// The records written, by type.
+    pub records_by_type: RecordsByType,
+    /// The bytes written, including records written to the fallback writer.
+    pub bytes_written: u64,
+    /// The records that could not be formatted.
+    pub serialization_errors: u64,
+    /// The failed attempts to write a record, to the primary writer or to the fallback one.
+    pub write_errors: u64,
+    /// The records that were lost, because they could not be formatted or written.
+    pub dropped_records: u64,
+}
+
+impl BunyanStats {
+    /// The total number of records written.
+    pub fn records_written(&self) -> u64 {
+        let RecordsByType {
+            start,
+            end,
+            enter,
+            exit,
+            event,
+        } = self.records_by_type;
+        start + end + enter + exit + event
+    }
+}
+
+/// The records written by a `BunyanFormattingLayer`, by the name of their level.
+///
+/// Events promoted to Bunyan's FATAL level are only counted as `fatal`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RecordsByLevel {
+    pub trace: u64,
+    pub debug: u64,
+    pub info: u64,
+    pub warn: u64,
+    pub error: u64,
+    pub fatal: u64,
+}
+
+/// The records written by a `BunyanFormattingLayer`, by [`Type`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct RecordsByType {
+    /// [`Type::EnterSpan`] records.
+    pub start: u64,
+    /// [`Type::ExitSpan`] records.
+    pub end: u64,
+    /// [`Type::SpanEntered`] records.
+    pub enter: u64,
+    /// [`Type::SpanExited`] records.
+    pub exit: u64,
+    /// [`Type::Event`] records.
+    pub event: u64,
+}
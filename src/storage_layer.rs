@@ -1,6 +1,7 @@
 use std::collections::HashMap;
 use std::fmt;
-use std::time::Instant;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 use tracing::field::{Field, Visit};
 use tracing::span::{Attributes, Record};
 use tracing::{Id, Subscriber};
@@ -12,8 +13,140 @@ use tracing_subscriber::Layer;
 /// It's purpose is to store the fields associated to spans in an easy-to-consume format
 /// for downstream layers concerned with emitting a formatted representation of
 /// spans or events.
+///
+/// `JsonStorageLayer` can be used as-is, with its default configuration,
+/// or customised via its constructor:
+///
+/// ```rust
+/// use tracing_bunyan_formatter::{ElapsedPrecision, JsonStorageLayer};
+/// use tracing_subscriber::Registry;
+/// use tracing_subscriber::layer::SubscriberExt;
+///
+/// let subscriber = Registry::default().with(JsonStorageLayer);
+/// let subscriber = Registry::default()
+///     .with(JsonStorageLayer::new().elapsed_precision(ElapsedPrecision::Microseconds));
+/// ```
 #[derive(Clone, Debug)]
-pub struct JsonStorageLayer;
+pub struct JsonStorageLayer {
+    elapsed_precision: ElapsedPrecision,
+    track_busy_idle: bool,
+    field_inheritance: FieldInheritance,
+}
+
+/// A `JsonStorageLayer` with the default configuration.
+#[allow(non_upper_case_globals)]
+pub const JsonStorageLayer: JsonStorageLayer = JsonStorageLayer::new();
+
+impl JsonStorageLayer {
+    /// Create a `JsonStorageLayer` with the default configuration.
+    pub const fn new() -> Self {
+        Self {
+            elapsed_precision: ElapsedPrecision::Milliseconds,
+            track_busy_idle: false,
+            field_inheritance: FieldInheritance::All,
+        }
+    }
+
+    /// Choose the precision used to record how long each span took.
+    /// See [`ElapsedPrecision`] for the available options.
+    pub const fn elapsed_precision(mut self, precision: ElapsedPrecision) -> Self {
+        self.elapsed_precision = precision;
+        self
+    }
+
+    /// Whether to record how long each span was busy (i.e. entered) and idle (i.e. alive, but
+    /// not entered), in nanoseconds, as `busy_ns` and `idle_ns`.
+    ///
+    /// Unlike `elapsed_milliseconds`, which measures wall time since the span was first entered,
+    /// this tells apart the time spent doing work from the time spent waiting,
+    /// e.g. for an async span suspended at `.await` points.
+    pub const fn track_busy_idle(mut self, value: bool) -> Self {
+        self.track_busy_idle = value;
+        self
+    }
+
+    /// Choose which fields spans inherit from their parent span.
+    /// See [`FieldInheritance`] for the available options.
+    pub fn field_inheritance(mut self, policy: FieldInheritance) -> Self {
+        self.field_inheritance = policy;
+        self
+    }
+}
+
+/// Which fields [`JsonStorageLayer`](struct@JsonStorageLayer) copies from a parent span into its children.
+///
+/// ```rust
+/// use tracing_bunyan_formatter::{FieldInheritance, JsonStorageLayer};
+///
+/// let storage_layer = JsonStorageLayer::new().field_inheritance(FieldInheritance::Allowlist(
+///     vec!["request_id".into(), "tenant_id".into()],
+/// ));
+/// ```
+#[non_exhaustive]
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub enum FieldInheritance {
+    /// Children inherit all the fields of their parent (the default).
+    #[default]
+    All,
+    /// Children don't inherit any field: each span only stores its own fields.
+    None,
+    /// Children only inherit the listed fields of their parent.
+    Allowlist(Vec<String>),
+}
+
+/// Busy and idle time accumulated by a span, stored in its extensions.
+struct SpanTimings {
+    busy: Duration,
+    idle: Duration,
+    last: Instant,
+}
+
+impl SpanTimings {
+    fn new() -> Self {
+        Self {
+            busy: Duration::ZERO,
+            idle: Duration::ZERO,
+            last: Instant::now(),
+        }
+    }
+
+    /// Add the time elapsed since the last transition to the idle time.
+    fn record_idle(&mut self) {
+        let now = Instant::now();
+        self.idle += now.saturating_duration_since(self.last);
+        self.last = now;
+    }
+
+    /// Add the time elapsed since the last transition to the busy time.
+    fn record_busy(&mut self) {
+        let now = Instant::now();
+        self.busy += now.saturating_duration_since(self.last);
+        self.last = now;
+    }
+}
+
+impl Default for JsonStorageLayer {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The precision used by [`JsonStorageLayer`](struct@JsonStorageLayer) to record the duration of spans.
+///
+/// `elapsed_milliseconds` is always recorded: a higher precision adds a further field.
+#[non_exhaustive]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum ElapsedPrecision {
+    /// Whole milliseconds, as `elapsed_milliseconds`.
+    #[default]
+    Milliseconds,
+    /// Whole milliseconds, as `elapsed_milliseconds`, and whole microseconds, as `elapsed_microseconds`.
+    Microseconds,
+    /// Whole milliseconds, as `elapsed_milliseconds`, and whole nanoseconds, as `elapsed_nanoseconds`.
+    Nanoseconds,
+    /// Fractional milliseconds (e.g. `0.153`) as `elapsed_milliseconds`.
+    FractionalMilliseconds,
+}
 
 /// `JsonStorage` will collect information about a span when it's created (`new_span` handler)
 /// or when new records are attached to it (`on_record` handler) and store it in its `extensions`
@@ -24,16 +157,67 @@ pub struct JsonStorageLayer;
 /// the underlying BTreeMap using its public API.
 ///
 /// For spans, we also store the duration of each span with the `elapsed_milliseconds` key using
-/// the `on_exit`/`on_enter` handlers.
+/// the `on_exit`/`on_enter` handlers (see [`ElapsedPrecision`] for higher precisions) and,
+/// if enabled via [`JsonStorageLayer::track_busy_idle`], their busy and idle time with the
+/// `busy_ns` and `idle_ns` keys.
+///
+/// Children don't copy the fields of their parent: they hold a reference-counted pointer to
+/// them, alongside their own fields, and lookups walk up the chain lazily.
+/// A span's own fields are copied only if they are modified after a child took a reference
+/// to them (copy-on-write), so that children keep seeing the fields of their parent as they
+/// were when they were created.
 #[derive(Clone, Debug)]
 pub struct JsonStorage<'a> {
-    values: HashMap<&'a str, serde_json::Value>,
+    values: Arc<HashMap<&'a str, serde_json::Value>>,
+    parent: Option<Arc<JsonStorage<'a>>>,
 }
 
 impl<'a> JsonStorage<'a> {
-    /// Get the set of stored values, as a set of keys and JSON values.
-    pub fn values(&self) -> &HashMap<&'a str, serde_json::Value> {
-        &self.values
+    /// Get an iterator over the stored values, as keys and JSON values.
+    ///
+    /// Values inherited from the parent span are included, unless they are overridden.
+    pub fn values(&self) -> JsonStorageValues<'_, 'a> {
+        JsonStorageValues {
+            head: self,
+            current: self,
+            depth: 0,
+            iter: self.values.iter(),
+        }
+    }
+
+    /// Get the value stored for `key`, if any, looking it up in the parent span if it's not
+    /// set on the span itself.
+    pub fn get(&self, key: &str) -> Option<&serde_json::Value> {
+        let mut storage = self;
+        loop {
+            if let Some(value) = storage.values.get(key) {
+                return Some(value);
+            }
+            storage = storage.parent.as_deref()?;
+        }
+    }
+
+    /// Create an empty storage inheriting all the values of `parent`, without copying them.
+    pub(crate) fn child_of(parent: &JsonStorage<'a>) -> Self {
+        Self {
+            values: Arc::default(),
+            parent: Some(Arc::new(parent.clone())),
+        }
+    }
+
+    /// Create a storage holding a copy of the given values.
+    pub(crate) fn from_values(
+        values: impl IntoIterator<Item = (&'a str, serde_json::Value)>,
+    ) -> Self {
+        Self {
+            values: Arc::new(values.into_iter().collect()),
+            parent: None,
+        }
+    }
+
+    /// Store a value, overriding any value with the same key (including inherited ones).
+    pub(crate) fn insert(&mut self, key: &'a str, value: serde_json::Value) {
+        Arc::make_mut(&mut self.values).insert(key, value);
     }
 }
 
@@ -41,7 +225,55 @@ impl<'a> JsonStorage<'a> {
 impl Default for JsonStorage<'_> {
     fn default() -> Self {
         Self {
-            values: HashMap::new(),
+            values: Arc::default(),
+            parent: None,
+        }
+    }
+}
+
+/// An iterator over the values of a [`JsonStorage`], including inherited ones.
+///
+/// Returned by [`JsonStorage::values`].
+#[derive(Debug)]
+pub struct JsonStorageValues<'s, 'a> {
+    head: &'s JsonStorage<'a>,
+    current: &'s JsonStorage<'a>,
+    depth: usize,
+    iter: std::collections::hash_map::Iter<'s, &'a str, serde_json::Value>,
+}
+
+impl JsonStorageValues<'_, '_> {
+    /// Whether `key` is set by a span closer to the head than the one being iterated over.
+    fn is_overridden(&self, key: &str) -> bool {
+        let mut storage = self.head;
+        for _ in 0..self.depth {
+            if storage.values.contains_key(key) {
+                return true;
+            }
+            match storage.parent.as_deref() {
+                Some(parent) => storage = parent,
+                None => return false,
+            }
+        }
+        false
+    }
+}
+
+impl<'s, 'a> Iterator for JsonStorageValues<'s, 'a> {
+    type Item = (&'a str, &'s serde_json::Value);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some((key, value)) = self.iter.next() {
+                if !self.is_overridden(key) {
+                    return Some((*key, value));
+                }
+                continue;
+            }
+            let parent = self.current.parent.as_deref()?;
+            self.current = parent;
+            self.depth += 1;
+            self.iter = parent.values.iter();
         }
     }
 }
@@ -51,32 +283,27 @@ impl Default for JsonStorage<'_> {
 impl Visit for JsonStorage<'_> {
     /// Visit a signed 64-bit integer value.
     fn record_i64(&mut self, field: &Field, value: i64) {
-        self.values
-            .insert(field.name(), serde_json::Value::from(value));
+        self.insert(field.name(), serde_json::Value::from(value));
     }
 
     /// Visit an unsigned 64-bit integer value.
     fn record_u64(&mut self, field: &Field, value: u64) {
-        self.values
-            .insert(field.name(), serde_json::Value::from(value));
+        self.insert(field.name(), serde_json::Value::from(value));
     }
 
     /// Visit a 64-bit floating point value.
     fn record_f64(&mut self, field: &Field, value: f64) {
-        self.values
-            .insert(field.name(), serde_json::Value::from(value));
+        self.insert(field.name(), serde_json::Value::from(value));
     }
 
     /// Visit a boolean value.
     fn record_bool(&mut self, field: &Field, value: bool) {
-        self.values
-            .insert(field.name(), serde_json::Value::from(value));
+        self.insert(field.name(), serde_json::Value::from(value));
     }
 
     /// Visit a string value.
     fn record_str(&mut self, field: &Field, value: &str) {
-        self.values
-            .insert(field.name(), serde_json::Value::from(value));
+        self.insert(field.name(), serde_json::Value::from(value));
     }
 
     fn record_debug(&mut self, field: &Field, value: &dyn fmt::Debug) {
@@ -84,12 +311,10 @@ impl Visit for JsonStorage<'_> {
             // Skip fields that are actually log metadata that have already been handled
             name if name.starts_with("log.") => (),
             name if name.starts_with("r#") => {
-                self.values
-                    .insert(&name[2..], serde_json::Value::from(format!("{:?}", value)));
+                self.insert(&name[2..], serde_json::Value::from(format!("{:?}", value)));
             }
             name => {
-                self.values
-                    .insert(name, serde_json::Value::from(format!("{:?}", value)));
+                self.insert(name, serde_json::Value::from(format!("{:?}", value)));
             }
         };
     }
@@ -101,7 +326,7 @@ impl Visit for JsonStorage<'_> {
 
         match serde_json::to_value(serializable) {
             Ok(json_value) => {
-                self.values.insert(field.name(), json_value);
+                self.insert(field.name(), json_value);
             }
             Err(error) => {
                 tracing::debug!(
@@ -129,15 +354,27 @@ impl<S: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>> Layer
     fn on_new_span(&self, attrs: &Attributes<'_>, id: &Id, ctx: Context<'_, S>) {
         let span = ctx.span(id).expect("Span not found, this is a bug");
 
-        // We want to inherit the fields from the parent span, if there is one.
-        let mut visitor = if let Some(parent_span) = span.parent() {
+        // We want to inherit the fields from the parent span, if there is one,
+        // according to the configured inheritance policy.
+        let parent_span = match self.field_inheritance {
+            FieldInheritance::None => None,
+            _ => span.parent(),
+        };
+        let mut visitor = if let Some(parent_span) = parent_span {
             // Extensions can be used to associate arbitrary data to a span.
             // We'll use it to store our representation of its fields.
-            // We create a copy of the parent visitor!
+            // The child references the fields of its parent, they are not copied!
             let mut extensions = parent_span.extensions_mut();
             extensions
                 .get_mut::<JsonStorage>()
-                .map(|v| v.to_owned())
+                .map(|v| match &self.field_inheritance {
+                    FieldInheritance::Allowlist(keys) => JsonStorage::from_values(
+                        v.values()
+                            .filter(|(key, _)| keys.iter().any(|k| k == key))
+                            .map(|(key, value)| (key, value.clone())),
+                    ),
+                    _ => JsonStorage::child_of(v),
+                })
                 .unwrap_or_default()
         } else {
             JsonStorage::default()
@@ -150,6 +387,10 @@ impl<S: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>> Layer
         attrs.record(&mut visitor);
         // Associate the visitor with the Span for future usage via the Span's extensions
         extensions.insert(visitor);
+
+        if self.track_busy_idle {
+            extensions.insert(SpanTimings::new());
+        }
     }
 
     fn on_record(&self, span: &Id, values: &Record<'_>, ctx: Context<'_, S>) {
@@ -174,40 +415,96 @@ impl<S: Subscriber + for<'a> tracing_subscriber::registry::LookupSpan<'a>> Layer
         if extensions.get_mut::<Instant>().is_none() {
             extensions.insert(Instant::now());
         }
+        if let Some(timings) = extensions.get_mut::<SpanTimings>() {
+            timings.record_idle();
+        }
+    }
+
+    /// When we exit a span, account for the time it was busy.
+    fn on_exit(&self, span: &Id, ctx: Context<'_, S>) {
+        let span = ctx.span(span).expect("Span not found, this is a bug");
+
+        let mut extensions = span.extensions_mut();
+        if let Some(timings) = extensions.get_mut::<SpanTimings>() {
+            timings.record_busy();
+        }
     }
 
-    /// When we close a span, register how long it took in milliseconds.
+    /// When we close a span, register how long it took in milliseconds (or with the configured precision).
     fn on_close(&self, span: Id, ctx: Context<'_, S>) {
         let span = ctx.span(&span).expect("Span not found, this is a bug");
 
         // Using a block to drop the immutable reference to extensions
         // given that we want to borrow it mutably just below
-        let elapsed_milliseconds = {
+        let elapsed = {
             let extensions = span.extensions();
             extensions
                 .get::<Instant>()
-                .map(|i| i.elapsed().as_millis())
+                .map(|i| i.elapsed())
                 // If `Instant` is not in the span extensions it means that the span was never
                 // entered into.
-                .unwrap_or(0)
-        };
-
-        #[cfg(not(feature = "arbitrary-precision"))]
-        // without the arbitrary_precision feature u128 values are not supported,
-        // but u64 is still more than enough for our purposes
-        let elapsed_milliseconds: u64 = {
-            use std::convert::TryInto;
-
-            elapsed_milliseconds.try_into().unwrap_or_default()
+                .unwrap_or_default()
         };
 
         let mut extensions_mut = span.extensions_mut();
+        let timings = extensions_mut.get_mut::<SpanTimings>().map(|timings| {
+            timings.record_idle();
+            (timings.busy, timings.idle)
+        });
         let visitor = extensions_mut
             .get_mut::<JsonStorage>()
             .expect("Visitor not found on 'record', this is a bug");
 
-        if let Ok(elapsed) = serde_json::to_value(elapsed_milliseconds) {
-            visitor.values.insert("elapsed_milliseconds", elapsed);
+        if let Some((busy, idle)) = timings {
+            if let Some(busy) = to_json_integer(busy.as_nanos()) {
+                visitor.insert("busy_ns", busy);
+            }
+            if let Some(idle) = to_json_integer(idle.as_nanos()) {
+                visitor.insert("idle_ns", idle);
+            }
+        }
+
+        match self.elapsed_precision {
+            ElapsedPrecision::FractionalMilliseconds => {
+                let elapsed_milliseconds = elapsed.as_secs_f64() * 1000.0;
+                visitor.insert(
+                    "elapsed_milliseconds",
+                    serde_json::Value::from(elapsed_milliseconds),
+                );
+            }
+            precision => {
+                if let Some(elapsed) = to_json_integer(elapsed.as_millis()) {
+                    visitor.insert("elapsed_milliseconds", elapsed);
+                }
+                let high_resolution = match precision {
+                    ElapsedPrecision::Microseconds => {
+                        Some(("elapsed_microseconds", elapsed.as_micros()))
+                    }
+                    ElapsedPrecision::Nanoseconds => {
+                        Some(("elapsed_nanoseconds", elapsed.as_nanos()))
+                    }
+                    _ => None,
+                };
+                if let Some((key, value)) = high_resolution {
+                    if let Some(value) = to_json_integer(value) {
+                        visitor.insert(key, value);
+                    }
+                }
+            }
         }
     }
 }
+
+/// Convert a duration, expressed as an integer, to a JSON value.
+fn to_json_integer(value: u128) -> Option<serde_json::Value> {
+    #[cfg(not(feature = "arbitrary-precision"))]
+    // without the arbitrary_precision feature u128 values are not supported,
+    // but u64 is still more than enough for our purposes
+    let value: u64 = {
+        use std::convert::TryInto;
+
+        value.try_into().unwrap_or_default()
+    };
+
+    serde_json::to_value(value).ok()
+}
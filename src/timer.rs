@@ -0,0 +1,138 @@
+use time::format_description::well_known::Rfc3339;
+use time::format_description::{FormatItem, OwnedFormatItem};
+use time::macros::format_description;
+use time::{OffsetDateTime, UtcOffset};
+
+/// RFC 3339 with exactly three subsecond digits and a `Z` suffix, as produced by node-bunyan.
+const RFC3339_MILLIS_UTC: &[FormatItem<'_>] =
+    format_description!("[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:3]Z");
+/// RFC 3339 with exactly three subsecond digits and a numeric offset.
+const RFC3339_MILLIS_OFFSET: &[FormatItem<'_>] = format_description!(
+    "[year]-[month]-[day]T[hour]:[minute]:[second].[subsecond digits:3][offset_hour sign:mandatory]:[offset_minute]"
+);
+
+/// A `Timer` provides the value of the `time` field of each formatted record.
+///
+/// It is split in two steps: getting the current time (`now`) and turning it into a string
+/// (`format_time`), so that you can swap the clock (e.g. to get deterministic output in tests)
+/// and/or the format (e.g. to use a local offset) independently.
+///
+/// ```rust
+/// use time::OffsetDateTime;
+/// use tracing_bunyan_formatter::{BunyanFormattingLayer, Timer};
+///
+/// struct FixedClock;
+///
+/// impl Timer for FixedClock {
+///     fn now(&self) -> OffsetDateTime {
+///         OffsetDateTime::UNIX_EPOCH
+///     }
+/// }
+///
+/// let formatting_layer = BunyanFormattingLayer::builder("test")
+///     .timer(FixedClock)
+///     .build()
+///     .unwrap();
+/// ```
+pub trait Timer: Send + Sync + 'static {
+    /// The current time. Defaults to the system clock, in UTC.
+    fn now(&self) -> OffsetDateTime {
+        OffsetDateTime::now_utc()
+    }
+
+    /// Format a point in time for the `time` field. Defaults to RFC 3339.
+    ///
+    /// If formatting fails the record falls back to RFC 3339, given that `time` is
+    /// a required core field of the Bunyan format.
+    fn format_time(&self, time: OffsetDateTime) -> Result<String, time::error::Format> {
+        time.format(&Rfc3339)
+    }
+}
+
+impl Default for Box<dyn Timer> {
+    fn default() -> Self {
+        Box::new(SystemTimer::default())
+    }
+}
+
+/// The system clock, converted to a given offset (UTC by default) and formatted as
+/// RFC 3339 (by default) or according to a custom format description.
+///
+/// ```rust
+/// use time::UtcOffset;
+/// use tracing_bunyan_formatter::SystemTimer;
+///
+/// // What node-bunyan produces, e.g. `2023-03-29T18:34:38.445Z`
+/// let timer = SystemTimer::utc().with_millis();
+/// // RFC 3339 with a fixed offset, e.g. `2023-03-29T20:34:38.445454908+02:00`
+/// let timer = SystemTimer::with_offset(UtcOffset::from_hms(2, 0, 0).unwrap());
+/// ```
+#[derive(Clone, Debug)]
+pub struct SystemTimer {
+    offset: UtcOffset,
+    format: TimeFormat,
+}
+
+#[derive(Clone, Debug)]
+enum TimeFormat {
+    Rfc3339,
+    Rfc3339Millis,
+    Custom(OwnedFormatItem),
+}
+
+impl SystemTimer {
+    /// Use the system clock in UTC.
+    pub fn utc() -> Self {
+        Self::with_offset(UtcOffset::UTC)
+    }
+
+    /// Use the system clock with the current local offset.
+    ///
+    /// The offset is determined once, when calling this function: it fails if the local offset
+    /// can't be determined soundly (e.g. on some platforms when multiple threads are running).
+    pub fn local() -> Result<Self, time::error::IndeterminateOffset> {
+        UtcOffset::current_local_offset().map(Self::with_offset)
+    }
+
+    /// Use the system clock with a fixed offset.
+    pub fn with_offset(offset: UtcOffset) -> Self {
+        Self {
+            offset,
+            format: TimeFormat::Rfc3339,
+        }
+    }
+
+    /// Format as RFC 3339 with a millisecond precision (e.g. `2023-03-29T18:34:38.445Z`),
+    /// matching node-bunyan.
+    pub fn with_millis(mut self) -> Self {
+        self.format = TimeFormat::Rfc3339Millis;
+        self
+    }
+
+    /// Format according to a custom [`time` format description](https://time-rs.github.io/book/api/format-description.html).
+    pub fn with_format(mut self, format: OwnedFormatItem) -> Self {
+        self.format = TimeFormat::Custom(format);
+        self
+    }
+}
+
+impl Default for SystemTimer {
+    fn default() -> Self {
+        Self::utc()
+    }
+}
+
+impl Timer for SystemTimer {
+    fn now(&self) -> OffsetDateTime {
+        OffsetDateTime::now_utc().to_offset(self.offset)
+    }
+
+    fn format_time(&self, time: OffsetDateTime) -> Result<String, time::error::Format> {
+        match &self.format {
+            TimeFormat::Rfc3339 => time.format(&Rfc3339),
+            TimeFormat::Rfc3339Millis if time.offset().is_utc() => time.format(RFC3339_MILLIS_UTC),
+            TimeFormat::Rfc3339Millis => time.format(RFC3339_MILLIS_OFFSET),
+            TimeFormat::Custom(format) => time.format(format),
+        }
+    }
+}
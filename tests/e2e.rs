@@ -1,11 +1,18 @@
-use crate::mock_writer::MockWriter;
+use crate::mock_writer::{MockMakeWriter, MockWriter};
 use claims::assert_some_eq;
 use serde_json::{json, Value};
 use std::collections::HashMap;
 use std::sync::{Arc, Mutex};
+use std::time::Duration;
 use time::format_description::well_known::Rfc3339;
+use time::UtcOffset;
 use tracing::{info, span, Level};
-use tracing_bunyan_formatter::{BunyanFormattingLayer, JsonStorageLayer};
+use tracing_bunyan_formatter::{
+    BunyanFormattingLayer, ConfigError, ElapsedPrecision, FieldInheritance, JsonStorageLayer,
+    LevelMapping, MessageFallback, MessageFormatter, NonBlockingBuilder, OverflowPolicy,
+    PlainMessageFormatter, SkipRule, SpanEvents, SpanInfo, SystemTimer, Timer, Type,
+};
+use tracing_subscriber::fmt::MakeWriter;
 use tracing_subscriber::layer::SubscriberExt;
 use tracing_subscriber::Registry;
 
@@ -58,6 +65,47 @@ fn run_and_get_output<F: Fn()>(action: F, use_span_ids: bool) -> Vec<Value> {
         .collect()
 }
 
+// Run a closure on top of `JsonStorageLayer` and a formatting layer configured by `configure`,
+// then collect the output as structured new-line-delimited JSON.
+fn run_with_layer<F, C>(configure: C, action: F) -> Vec<Value>
+where
+    F: Fn(),
+    C: FnOnce(MockMakeWriter) -> BunyanFormattingLayer<MockMakeWriter>,
+{
+    run_with_layers(Some(JsonStorageLayer), configure, action)
+}
+
+// Same as `run_with_layer`, with a custom-configured `JsonStorageLayer` (or none at all).
+fn run_with_layers<F, C>(
+    storage_layer: Option<JsonStorageLayer>,
+    configure: C,
+    action: F,
+) -> Vec<Value>
+where
+    F: Fn(),
+    C: FnOnce(MockMakeWriter) -> BunyanFormattingLayer<MockMakeWriter>,
+{
+    let make_writer = MockMakeWriter::default();
+    let formatting_layer = configure(make_writer.clone());
+    let subscriber = Registry::default()
+        .with(storage_layer)
+        .with(formatting_layer);
+    tracing::subscriber::with_default(subscriber, action);
+
+    parse_output(&make_writer)
+}
+
+// Parse the records written to `make_writer` as structured new-line-delimited JSON.
+fn parse_output(make_writer: &MockMakeWriter) -> Vec<Value> {
+    make_writer
+        .get_string()
+        .lines()
+        .filter(|&l| !l.trim().is_empty())
+        .inspect(|l| println!("{}", l))
+        .map(|line| serde_json::from_str::<Value>(line).unwrap())
+        .collect()
+}
+
 // Instrumented code to be run to test the behaviour of the tracing instrumentation.
 fn test_action() {
     let a = 2;
@@ -209,6 +257,40 @@ fn span_ids_are_recorded() {
     }
 }
 
+#[test]
+fn span_records_are_complete_without_storage_layer() {
+    let action = || {
+        let span = span!(Level::DEBUG, "parent_span", parent_property = 2, late = 0);
+        let _enter = span.enter();
+        span.record("late", 1);
+    };
+    let tracing_output = run_with_layers(
+        None,
+        |make_writer| {
+            BunyanFormattingLayer::builder("test")
+                .writer(make_writer)
+                .serialize_span_type(true)
+                .store_span_fields(true)
+                .build()
+                .unwrap()
+        },
+        action,
+    );
+
+    let span_record = |span_type: &str| {
+        tracing_output
+            .iter()
+            .find(|record| record.get("span_type") == Some(&json!(span_type)))
+            .unwrap()
+    };
+    let start = span_record("START");
+    assert_eq!(start.get("parent_property"), Some(&json!(2)));
+    assert_eq!(start.get("late"), Some(&json!(0)));
+    let end = span_record("END");
+    assert_eq!(end.get("parent_property"), Some(&json!(2)));
+    assert_eq!(end.get("late"), Some(&json!(1)));
+}
+
 #[test]
 fn elapsed_milliseconds_are_present_on_exit_span() {
     let tracing_output = run_and_get_output(test_action, false);
@@ -237,8 +319,8 @@ fn skip_fields() {
 fn skipping_core_fields_is_not_allowed() {
     let skipped_fields = vec!["level"];
 
-    let result = BunyanFormattingLayer::new("test".into(), || vec![])
-        .skip_fields(skipped_fields.into_iter());
+    let result =
+        BunyanFormattingLayer::new("test".into(), Vec::new).skip_fields(skipped_fields.into_iter());
 
     match result {
         Err(err) => {
@@ -251,6 +333,1242 @@ fn skipping_core_fields_is_not_allowed() {
     }
 }
 
+#[test]
+fn source_location_is_nested_under_src_when_enabled() {
+    let tracing_output = run_with_layer(
+        |make_writer| BunyanFormattingLayer::new("test".into(), make_writer).serialize_src(true),
+        test_action,
+    );
+
+    for record in tracing_output {
+        assert!(record.get("target").is_none());
+        assert!(record.get("line").is_none());
+        assert!(record.get("file").is_none());
+        let src = record.get("src").unwrap();
+        assert_eq!(
+            src.get("file"),
+            Some(&json!(format!("tests{}e2e.rs", std::path::MAIN_SEPARATOR)))
+        );
+        assert!(src.get("line").and_then(Value::as_u64).is_some());
+        assert_eq!(src.get("func"), Some(&json!("e2e")));
+    }
+}
+
+#[test]
+fn src_can_be_skipped() {
+    let tracing_output = run_with_layer(
+        |make_writer| {
+            BunyanFormattingLayer::new("test".into(), make_writer)
+                .serialize_src(true)
+                .skip_fields(vec!["src"].into_iter())
+                .unwrap()
+        },
+        test_action,
+    );
+
+    for record in tracing_output {
+        assert!(record.get("src").is_none());
+    }
+}
+
+#[test]
+fn builder_applies_all_options() {
+    let tracing_output = run_with_layer(
+        |make_writer| {
+            BunyanFormattingLayer::builder("test")
+                .writer(make_writer)
+                .default_field("custom_field", json!("custom_value"))
+                .skip_fields(["skipped"])
+                .hostname("my-host")
+                .pid(42)
+                .timestamp_format("[year]-[month]-[day]")
+                .build()
+                .unwrap()
+        },
+        test_action,
+    );
+
+    for record in tracing_output {
+        assert_eq!(record.get("hostname"), Some(&json!("my-host")));
+        assert_eq!(record.get("pid"), Some(&json!(42)));
+        assert_eq!(record.get("custom_field"), Some(&json!("custom_value")));
+        assert!(record.get("skipped").is_none());
+        let time = record.get("time").and_then(Value::as_str).unwrap();
+        assert_eq!(time.len(), "yyyy-mm-dd".len());
+    }
+}
+
+#[test]
+fn builder_reports_all_configuration_errors() {
+    let result = BunyanFormattingLayer::builder("")
+        .skip_fields(["level", "skipped", "msg"])
+        .default_field("pid", json!(1))
+        .timestamp_format("[year")
+        .build();
+
+    let errors = match result {
+        Err(err) => err.errors().to_vec(),
+        Ok(_) => panic!("an invalid configuration shouldn't build"),
+    };
+    assert_eq!(errors.len(), 5);
+    assert_eq!(errors[0], ConfigError::EmptyName);
+    assert_eq!(errors[1], ConfigError::SkipCoreField("level".into()));
+    assert_eq!(errors[2], ConfigError::SkipCoreField("msg".into()));
+    assert_eq!(errors[3], ConfigError::ReservedDefaultField("pid".into()));
+    assert!(matches!(
+        errors[4],
+        ConfigError::InvalidTimestampFormat { .. }
+    ));
+}
+
+struct FixedClock;
+
+impl Timer for FixedClock {
+    fn now(&self) -> time::OffsetDateTime {
+        time::OffsetDateTime::parse("2023-03-29T18:34:38.445454908Z", &Rfc3339).unwrap()
+    }
+}
+
+#[test]
+fn a_deterministic_timer_produces_snapshottable_records() {
+    let tracing_output = run_with_layer(
+        |make_writer| {
+            BunyanFormattingLayer::builder("test")
+                .writer(make_writer)
+                .hostname("my-host")
+                .pid(42)
+                .skip_fields(["line"])
+                .timer(FixedClock)
+                .build()
+                .unwrap()
+        },
+        || info!(answer = 42, "Hello"),
+    );
+
+    assert_eq!(
+        tracing_output,
+        vec![json!({
+            "v": 0,
+            "name": "test",
+            "msg": "Hello",
+            "level": 30,
+            "hostname": "my-host",
+            "pid": 42,
+            "time": "2023-03-29T18:34:38.445454908Z",
+            "target": "e2e",
+            "file": format!("tests{}e2e.rs", std::path::MAIN_SEPARATOR),
+            "answer": 42,
+        })]
+    );
+}
+
+#[test]
+fn system_timer_can_truncate_to_milliseconds() {
+    let tracing_output = run_with_layer(
+        |make_writer| {
+            BunyanFormattingLayer::builder("test")
+                .writer(make_writer)
+                .timer(
+                    SystemTimer::with_offset(UtcOffset::from_hms(2, 0, 0).unwrap()).with_millis(),
+                )
+                .build()
+                .unwrap()
+        },
+        test_action,
+    );
+
+    for record in tracing_output {
+        let time = record.get("time").and_then(Value::as_str).unwrap();
+        assert_eq!(time.len(), "2023-03-29T20:34:38.445+02:00".len());
+        assert!(time.ends_with("+02:00"));
+        assert!(time::OffsetDateTime::parse(time, &Rfc3339).is_ok());
+    }
+}
+
+#[test]
+fn span_start_time_is_present_on_exit_span_when_enabled() {
+    let tracing_output = run_with_layer(
+        |make_writer| {
+            BunyanFormattingLayer::builder("test")
+                .writer(make_writer)
+                .serialize_span_start_time(true)
+                .build()
+                .unwrap()
+        },
+        test_action,
+    );
+
+    let mut exit_records = 0;
+    for record in tracing_output {
+        let msg = record.get("msg").and_then(Value::as_str).unwrap();
+        if msg.ends_with("END]") {
+            exit_records += 1;
+            let parse = |key| {
+                let value = record.get(key).and_then(Value::as_str).unwrap();
+                time::OffsetDateTime::parse(value, &Rfc3339).unwrap()
+            };
+            assert!(parse("span_start_time") <= parse("time"));
+        } else {
+            assert!(record.get("span_start_time").is_none());
+        }
+    }
+    assert_eq!(exit_records, 2);
+}
+
+// A clock moving forward by one second every time it is read.
+#[derive(Default)]
+struct SteppingClock(std::sync::atomic::AtomicI64);
+
+impl Timer for SteppingClock {
+    fn now(&self) -> time::OffsetDateTime {
+        let step = self.0.fetch_add(1, std::sync::atomic::Ordering::Relaxed);
+        time::OffsetDateTime::UNIX_EPOCH + time::Duration::seconds(step)
+    }
+}
+
+#[test]
+fn span_start_time_is_read_from_the_timer() {
+    let tracing_output = run_with_layer(
+        |make_writer| {
+            BunyanFormattingLayer::builder("test")
+                .writer(make_writer)
+                .serialize_span_start_time(true)
+                .timer(SteppingClock::default())
+                .build()
+                .unwrap()
+        },
+        || {
+            let span = span!(Level::DEBUG, "request");
+            let _enter = span.enter();
+            info!("Hello");
+        },
+    );
+
+    let find = |suffix| {
+        tracing_output
+            .iter()
+            .find(|record| record["msg"].as_str().unwrap().ends_with(suffix))
+            .unwrap()
+    };
+    assert_eq!(find("START]")["time"], json!("1970-01-01T00:00:00Z"));
+    assert_eq!(
+        find("END]")["span_start_time"],
+        json!("1970-01-01T00:00:01Z")
+    );
+}
+
+#[test]
+fn elapsed_time_can_be_recorded_with_a_higher_precision() {
+    let precisions = [
+        (ElapsedPrecision::Microseconds, "elapsed_microseconds"),
+        (ElapsedPrecision::Nanoseconds, "elapsed_nanoseconds"),
+    ];
+    for (precision, key) in precisions {
+        let tracing_output = run_with_layers(
+            Some(JsonStorageLayer::new().elapsed_precision(precision)),
+            |make_writer| BunyanFormattingLayer::new("test".into(), make_writer),
+            test_action,
+        );
+
+        for record in tracing_output {
+            if record
+                .get("msg")
+                .and_then(Value::as_str)
+                .map_or(false, |msg| msg.ends_with("END]"))
+            {
+                assert!(record.get("elapsed_milliseconds").unwrap().is_u64());
+                assert!(record.get(key).unwrap().is_u64());
+            }
+        }
+    }
+}
+
+#[test]
+fn elapsed_milliseconds_can_be_fractional() {
+    let tracing_output = run_with_layers(
+        Some(JsonStorageLayer::new().elapsed_precision(ElapsedPrecision::FractionalMilliseconds)),
+        |make_writer| BunyanFormattingLayer::new("test".into(), make_writer),
+        test_action,
+    );
+
+    for record in tracing_output {
+        if record
+            .get("msg")
+            .and_then(Value::as_str)
+            .map_or(false, |msg| msg.ends_with("END]"))
+        {
+            assert!(record.get("elapsed_milliseconds").unwrap().is_f64());
+        }
+    }
+}
+
+#[test]
+fn busy_and_idle_time_are_tracked_when_enabled() {
+    let action = || {
+        let span = span!(Level::DEBUG, "async_like_span");
+        for _ in 0..2 {
+            let _enter = span.enter();
+            std::thread::sleep(Duration::from_millis(10));
+            drop(_enter);
+            std::thread::sleep(Duration::from_millis(20));
+        }
+    };
+    let tracing_output = run_with_layers(
+        Some(JsonStorageLayer::new().track_busy_idle(true)),
+        |make_writer| BunyanFormattingLayer::new("test".into(), make_writer),
+        action,
+    );
+
+    let exit_record = tracing_output
+        .iter()
+        .find(|record| record.get("msg") == Some(&json!("[ASYNC_LIKE_SPAN - END]")))
+        .unwrap();
+    let busy_ns = exit_record.get("busy_ns").and_then(Value::as_u64).unwrap();
+    let idle_ns = exit_record.get("idle_ns").and_then(Value::as_u64).unwrap();
+    assert!(busy_ns >= 20_000_000);
+    assert!(idle_ns >= 40_000_000);
+}
+
+#[test]
+fn field_inheritance_can_be_restricted() {
+    let action = || {
+        let span = span!(Level::DEBUG, "parent_span", request_id = 1, payload = "big");
+        let _enter = span.enter();
+
+        let child_span = span!(Level::DEBUG, "child_span", child_property = 2);
+        let _enter_child = child_span.enter();
+
+        info!("in child");
+    };
+
+    let policies = [
+        (FieldInheritance::All, true, true),
+        (FieldInheritance::None, false, false),
+        (
+            FieldInheritance::Allowlist(vec!["request_id".into()]),
+            true,
+            false,
+        ),
+    ];
+    for (policy, has_request_id, has_payload) in policies {
+        let tracing_output = run_with_layers(
+            Some(JsonStorageLayer::new().field_inheritance(policy)),
+            |make_writer| BunyanFormattingLayer::new("test".into(), make_writer),
+            action,
+        );
+
+        let event = tracing_output
+            .iter()
+            .find(|record| record.get("msg") == Some(&json!("[CHILD_SPAN - EVENT] in child")))
+            .unwrap();
+        assert_eq!(event.get("child_property"), Some(&json!(2)));
+        assert_eq!(event.get("request_id").is_some(), has_request_id);
+        assert_eq!(event.get("payload").is_some(), has_payload);
+    }
+}
+
+#[test]
+fn child_fields_override_inherited_fields() {
+    let action = || {
+        let span = span!(
+            Level::DEBUG,
+            "parent_span",
+            a = 1,
+            b = 1,
+            late = tracing::field::Empty
+        );
+        let _enter = span.enter();
+
+        let child_span = span!(Level::DEBUG, "child_span", b = 2);
+        let _enter_child = child_span.enter();
+        span.record("late", 1);
+
+        info!("in child");
+    };
+    let raw_output = run_and_get_raw_output(action, false);
+
+    let event = raw_output
+        .lines()
+        .find(|line| line.contains("[CHILD_SPAN - EVENT] in child"))
+        .unwrap();
+    // Overridden fields are emitted only once
+    assert_eq!(event.matches("\"b\":").count(), 1);
+    let event: Value = serde_json::from_str(event).unwrap();
+    assert_eq!(event.get("a"), Some(&json!(1)));
+    assert_eq!(event.get("b"), Some(&json!(2)));
+    // Children see the fields of their parent as they were when they were created
+    assert!(event.get("late").is_none());
+}
+
+#[test]
+fn span_list_describes_the_full_hierarchy() {
+    let action = || {
+        info!("orphan");
+
+        let span = span!(Level::DEBUG, "parent_span", parent_property = 2);
+        let _enter = span.enter();
+
+        let child_span = span!(
+            Level::DEBUG,
+            "child_span",
+            skipped = true,
+            late = tracing::field::Empty
+        );
+        let _enter_child = child_span.enter();
+        child_span.record("late", "recorded");
+
+        info!("in child");
+    };
+    // The formatting layer collects span fields on its own, without `JsonStorageLayer`
+    let tracing_output = run_with_layers(
+        None,
+        |make_writer| {
+            BunyanFormattingLayer::builder("test")
+                .writer(make_writer)
+                .skip_fields(["skipped"])
+                .serialize_span_list(true)
+                .build()
+                .unwrap()
+        },
+        action,
+    );
+
+    let orphan = tracing_output
+        .iter()
+        .find(|record| record.get("msg") == Some(&json!("orphan")))
+        .unwrap();
+    assert!(orphan.get("spans").is_none());
+
+    let event = tracing_output
+        .iter()
+        .find(|record| record.get("msg") == Some(&json!("[CHILD_SPAN - EVENT] in child")))
+        .unwrap();
+    let spans = event.get("spans").and_then(Value::as_array).unwrap();
+    assert_eq!(spans.len(), 2);
+    assert_eq!(spans[0].get("name"), Some(&json!("parent_span")));
+    assert_eq!(spans[0].get("fields"), Some(&json!({"parent_property": 2})));
+    assert_eq!(spans[1].get("name"), Some(&json!("child_span")));
+    assert_eq!(spans[1].get("fields"), Some(&json!({"late": "recorded"})));
+    assert_ne!(spans[0].get("id"), spans[1].get("id"));
+}
+
+#[test]
+fn dotted_fields_can_be_nested() {
+    let action = || {
+        let span = span!(Level::DEBUG, "request", http.method = "GET", db = "main");
+        let _enter = span.enter();
+
+        info!(http.status = 200, db.statement = "SELECT 1", "done");
+    };
+    let tracing_output = run_with_layer(
+        |make_writer| {
+            BunyanFormattingLayer::builder("test")
+                .writer(make_writer)
+                .nest_dotted_fields(true)
+                .build()
+                .unwrap()
+        },
+        action,
+    );
+
+    let event = tracing_output
+        .iter()
+        .find(|record| record.get("msg") == Some(&json!("[REQUEST - EVENT] done")))
+        .unwrap();
+    assert_eq!(
+        event.get("http"),
+        Some(&json!({"method": "GET", "status": 200}))
+    );
+    // `db` is a plain value: `db.statement` can't be nested under it
+    assert_eq!(event.get("db"), Some(&json!("main")));
+    assert_eq!(event.get("db.statement"), Some(&json!("SELECT 1")));
+    assert!(event.get("http.method").is_none());
+}
+
+#[test]
+fn skip_rules_can_be_scoped_to_targets_and_spans() {
+    let action = || {
+        info!(
+            internal.retries = 1,
+            internal.queue = "a",
+            body = "hello",
+            "plain"
+        );
+        info!(target: "hyper::proto", body = "hello", "from hyper");
+        let span = span!(Level::DEBUG, "upload", payload = "big");
+        let _enter = span.enter();
+        info!(payload = "bigger", body = "hello", "uploading");
+    };
+    let tracing_output = run_with_layer(
+        |make_writer| {
+            BunyanFormattingLayer::builder("test")
+                .writer(make_writer)
+                .skip_fields(["internal.*"])
+                .skip_rule(SkipRule::new("body").for_target("hyper::*"))
+                .skip_rule(SkipRule::new("payload").in_span("upload"))
+                .build()
+                .unwrap()
+        },
+        action,
+    );
+
+    let plain = &tracing_output[0];
+    assert!(plain.get("internal.retries").is_none());
+    assert!(plain.get("internal.queue").is_none());
+    assert_eq!(plain.get("body"), Some(&json!("hello")));
+    let from_hyper = &tracing_output[1];
+    assert!(from_hyper.get("body").is_none());
+    for record in &tracing_output[2..] {
+        assert!(record.get("payload").is_none());
+    }
+    assert_eq!(tracing_output[3].get("body"), Some(&json!("hello")));
+}
+
+#[test]
+fn skip_rules_cannot_skip_core_fields() {
+    let result = BunyanFormattingLayer::builder("test")
+        .skip_fields(["m?g", "internal.*"])
+        .skip_rule(SkipRule::new("*").for_target("hyper::*"))
+        .build();
+
+    let errors = match result {
+        Err(err) => err.errors().to_vec(),
+        Ok(_) => panic!("an invalid configuration shouldn't build"),
+    };
+    assert_eq!(
+        errors,
+        vec![
+            ConfigError::SkipCoreField("m?g".into()),
+            ConfigError::SkipCoreField("*".into()),
+        ]
+    );
+    assert!(BunyanFormattingLayer::new("test".into(), std::io::stdout)
+        .skip_rule(SkipRule::new("l*"))
+        .is_err());
+}
+
+#[test]
+fn layers_on_the_same_subscriber_can_filter_records_differently() {
+    let verbose_writer = MockMakeWriter::default();
+    let terse_writer = MockMakeWriter::default();
+    let verbose_layer = BunyanFormattingLayer::builder("verbose")
+        .writer(verbose_writer.clone())
+        .filter("trace")
+        .build()
+        .unwrap();
+    let terse_layer = BunyanFormattingLayer::builder("terse")
+        .writer(terse_writer.clone())
+        .filter("warn,e2e::noisy=off,e2e::important=info")
+        .build()
+        .unwrap();
+    let subscriber = Registry::default()
+        .with(JsonStorageLayer)
+        .with(verbose_layer)
+        .with(terse_layer);
+    tracing::subscriber::with_default(subscriber, || {
+        let span = span!(Level::DEBUG, "shaving_yaks");
+        let _enter = span.enter();
+        info!("routine");
+        tracing::warn!("unusual");
+        tracing::error!(target: "e2e::noisy", "spurious");
+        info!(target: "e2e::important", "milestone");
+    });
+
+    let verbose_output = parse_output(&verbose_writer);
+    assert_eq!(verbose_output.len(), 6);
+    let terse_messages: Vec<_> = parse_output(&terse_writer)
+        .into_iter()
+        .map(|record| record["msg"].clone())
+        .collect();
+    assert_eq!(
+        terse_messages,
+        vec![
+            json!("[SHAVING_YAKS - EVENT] unusual"),
+            json!("[SHAVING_YAKS - EVENT] milestone"),
+        ]
+    );
+}
+
+#[test]
+fn invalid_filter_directives_are_reported() {
+    let result = BunyanFormattingLayer::builder("test")
+        .filter("e2e=loud")
+        .build();
+
+    let errors = match result {
+        Err(err) => err.errors().to_vec(),
+        Ok(_) => panic!("an invalid configuration shouldn't build"),
+    };
+    assert!(matches!(
+        &errors[..],
+        [ConfigError::InvalidFilter { directives, .. }] if directives == "e2e=loud"
+    ));
+}
+
+#[test]
+fn span_events_policy_controls_span_records() {
+    let action = || {
+        let span = span!(Level::DEBUG, "shaving_yaks");
+        {
+            let _enter = span.enter();
+            info!("first yak");
+        }
+        {
+            let _enter = span.enter();
+            info!("second yak");
+        }
+        drop(span);
+        let span = span!(target: "hyper::proto", Level::DEBUG, "decode");
+        let _enter = span.enter();
+    };
+    let tracing_output = run_with_layer(
+        |make_writer| {
+            BunyanFormattingLayer::builder("test")
+                .writer(make_writer)
+                .span_events(SpanEvents::ENTER | SpanEvents::END)
+                .target_span_events("hyper::*", SpanEvents::NONE)
+                .build()
+                .unwrap()
+        },
+        action,
+    );
+
+    let messages: Vec<_> = tracing_output
+        .iter()
+        .map(|record| record["msg"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        messages,
+        vec![
+            "[SHAVING_YAKS - ENTER]",
+            "[SHAVING_YAKS - EVENT] first yak",
+            "[SHAVING_YAKS - ENTER]",
+            "[SHAVING_YAKS - EVENT] second yak",
+            "[SHAVING_YAKS - END]",
+        ]
+    );
+}
+
+#[test]
+fn levels_can_be_remapped_and_promoted_to_fatal() {
+    let action = || {
+        let span = span!(Level::DEBUG, "shaving_yaks");
+        let _enter = span.enter();
+        tracing::debug!("debug");
+        tracing::warn!("warn");
+        tracing::error!(fatal = true, "fatal field");
+        tracing::error!(fatal = false, "not fatal");
+        info!(target: "e2e::shutdown", "fatal target");
+    };
+    let tracing_output = run_with_layer(
+        |make_writer| {
+            BunyanFormattingLayer::builder("test")
+                .writer(make_writer)
+                .level_mapping(LevelMapping {
+                    debug: 25,
+                    ..LevelMapping::default()
+                })
+                .fatal_field("fatal")
+                .fatal_target("e2e::shutdown*")
+                .build()
+                .unwrap()
+        },
+        action,
+    );
+
+    let levels: Vec<_> = tracing_output
+        .iter()
+        .map(|record| record["level"].as_u64().unwrap())
+        .collect();
+    // START and END records are DEBUG, like the span
+    assert_eq!(levels, vec![25, 25, 40, 60, 50, 60, 25]);
+}
+
+#[test]
+fn level_names_can_be_added_next_to_levels() {
+    let action = || {
+        tracing::warn!("warn");
+        tracing::error!(fatal = true, "fatal");
+    };
+    let tracing_output = run_with_layer(
+        |make_writer| {
+            BunyanFormattingLayer::builder("test")
+                .writer(make_writer)
+                .level_name("level_name")
+                .fatal_field("fatal")
+                .build()
+                .unwrap()
+        },
+        action,
+    );
+    assert_eq!(tracing_output[0].get("level_name"), Some(&json!("WARN")));
+    assert_eq!(tracing_output[1].get("level_name"), Some(&json!("FATAL")));
+
+    let tracing_output = run_with_layer(
+        |make_writer| {
+            BunyanFormattingLayer::builder("test")
+                .writer(make_writer)
+                .level_name("severity")
+                .skip_fields(["sev*"])
+                .build()
+                .unwrap()
+        },
+        action,
+    );
+    assert!(tracing_output[0].get("severity").is_none());
+
+    let errors = match BunyanFormattingLayer::builder("test")
+        .level_name("msg")
+        .build()
+    {
+        Err(err) => err.errors().to_vec(),
+        Ok(_) => panic!("an invalid configuration shouldn't build"),
+    };
+    assert_eq!(
+        errors,
+        vec![ConfigError::ReservedLevelNameKey("msg".into())]
+    );
+}
+
+struct Breadcrumb;
+
+impl MessageFormatter for Breadcrumb {
+    fn format_span(&self, span: &SpanInfo<'_>, ty: Type) -> String {
+        let request_id = span
+            .fields()
+            .and_then(|fields| fields.get("request_id"))
+            .map(|id| format!(" #{}", id))
+            .unwrap_or_default();
+        format!(
+            "[{}{} - {}]",
+            span.names_from_root().join(" > "),
+            request_id,
+            ty
+        )
+    }
+}
+
+#[test]
+fn messages_can_be_formatted_with_a_custom_formatter() {
+    let action = || {
+        let span = span!(Level::DEBUG, "request", request_id = 7);
+        let _enter = span.enter();
+        let child = span!(Level::DEBUG, "db_query");
+        let _enter_child = child.enter();
+        info!("querying");
+    };
+    let messages = |tracing_output: Vec<Value>| -> Vec<String> {
+        tracing_output
+            .into_iter()
+            .map(|record| record["msg"].as_str().unwrap().to_owned())
+            .collect()
+    };
+
+    let tracing_output = run_with_layer(
+        |make_writer| {
+            BunyanFormattingLayer::builder("test")
+                .writer(make_writer)
+                .message_formatter(Breadcrumb)
+                .build()
+                .unwrap()
+        },
+        action,
+    );
+    assert_eq!(
+        messages(tracing_output),
+        vec![
+            "[request #7 - START]",
+            // `request_id` is inherited by `db_query`
+            "[request > db_query #7 - START]",
+            "[request > db_query #7 - EVENT] querying",
+            "[request > db_query #7 - END]",
+            "[request #7 - END]",
+        ]
+    );
+
+    let tracing_output = run_with_layer(
+        |make_writer| {
+            BunyanFormattingLayer::builder("test")
+                .writer(make_writer)
+                .message_formatter(PlainMessageFormatter)
+                .build()
+                .unwrap()
+        },
+        action,
+    );
+    assert_eq!(
+        messages(tracing_output),
+        vec!["request", "db_query", "querying", "db_query", "request"]
+    );
+}
+
+#[test]
+fn events_without_a_message_use_the_configured_fallback() {
+    let action = || {
+        info!(user_id = 3, name = "ferris", skipped = true);
+    };
+    let fallbacks = [
+        (MessageFallback::Target, "e2e".to_owned()),
+        (
+            MessageFallback::MetadataName,
+            format!("event tests{}e2e.rs:", std::path::MAIN_SEPARATOR),
+        ),
+        (MessageFallback::Fields, "name=ferris user_id=3".to_owned()),
+        (MessageFallback::Empty, "".to_owned()),
+    ];
+    for (fallback, expected) in fallbacks {
+        let tracing_output = run_with_layer(
+            |make_writer| {
+                BunyanFormattingLayer::builder("test")
+                    .writer(make_writer)
+                    .skip_fields(["skipped"])
+                    .message_fallback(fallback)
+                    .build()
+                    .unwrap()
+            },
+            action,
+        );
+
+        let msg = tracing_output[0]["msg"].as_str().unwrap();
+        if fallback == MessageFallback::MetadataName {
+            assert!(msg.starts_with(&expected), "{}", msg);
+        } else {
+            assert_eq!(msg, expected);
+        }
+    }
+}
+
+#[test]
+fn non_string_messages_are_rendered_as_json() {
+    let action = || {
+        info!(message = 42, extra = "field");
+        info!(message = true);
+    };
+    let tracing_output = run_with_layer(
+        |make_writer| BunyanFormattingLayer::new("test".into(), make_writer),
+        action,
+    );
+
+    assert_eq!(tracing_output[0].get("msg"), Some(&json!("42")));
+    assert_eq!(tracing_output[0].get("extra"), Some(&json!("field")));
+    assert!(tracing_output[0].get("message").is_none());
+    assert!(tracing_output[0].get("msg_data").is_none());
+    assert_eq!(tracing_output[1].get("msg"), Some(&json!("true")));
+}
+
+#[test]
+fn non_string_messages_can_be_kept_as_structured_data() {
+    let action = || {
+        info!(message = 42);
+        info!("a string message");
+    };
+    let tracing_output = run_with_layer(
+        |make_writer| {
+            BunyanFormattingLayer::builder("test")
+                .writer(make_writer)
+                .serialize_message_data(true)
+                .build()
+                .unwrap()
+        },
+        action,
+    );
+
+    assert_eq!(tracing_output[0].get("msg"), Some(&json!("42")));
+    assert_eq!(tracing_output[0].get("msg_data"), Some(&json!(42)));
+    assert_eq!(
+        tracing_output[1].get("msg"),
+        Some(&json!("a string message"))
+    );
+    assert!(tracing_output[1].get("msg_data").is_none());
+}
+
+#[test]
+fn default_fields_can_be_computed_per_record() {
+    let color = Arc::new(Mutex::new("blue"));
+    let action = || {
+        let span = span!(Level::DEBUG, "deploying");
+        let _enter = span.enter();
+        info!("before");
+        *color.lock().unwrap() = "green";
+        info!("after");
+    };
+    let tracing_output = run_with_layer(
+        |make_writer| {
+            let color = color.clone();
+            BunyanFormattingLayer::builder("test")
+                .writer(make_writer)
+                .default_fields_provider(move |metadata: &tracing::Metadata<'_>| {
+                    vec![
+                        ("deployment_color".to_owned(), json!(*color.lock().unwrap())),
+                        ("record_name".to_owned(), json!(metadata.name())),
+                        // Core fields can't be overridden
+                        ("name".to_owned(), json!("overridden")),
+                    ]
+                })
+                .build()
+                .unwrap()
+        },
+        action,
+    );
+
+    let colors: Vec<_> = tracing_output
+        .iter()
+        .map(|record| record["deployment_color"].as_str().unwrap())
+        .collect();
+    assert_eq!(colors, vec!["blue", "blue", "green", "green"]);
+    assert_eq!(
+        tracing_output[0].get("record_name"),
+        Some(&json!("deploying"))
+    );
+    for record in &tracing_output {
+        assert_eq!(record.get("name"), Some(&json!("test")));
+    }
+}
+
+#[test]
+fn scoped_skip_rules_survive_a_skip_fields_reload() {
+    let make_writer = MockMakeWriter::default();
+    let formatting_layer = BunyanFormattingLayer::builder("test")
+        .writer(make_writer.clone())
+        .skip_fields(["payload"])
+        .skip_rule(SkipRule::new("body").in_span("upload"))
+        .build()
+        .unwrap();
+    let handle = formatting_layer.config_handle();
+    let subscriber = Registry::default()
+        .with(JsonStorageLayer)
+        .with(formatting_layer);
+    tracing::subscriber::with_default(subscriber, || {
+        let span = span!(Level::DEBUG, "upload");
+        let _enter = span.enter();
+        handle.set_skip_fields(["internal.*"]).unwrap();
+        info!(
+            body = "big",
+            payload = "big",
+            internal.retries = 1,
+            "before"
+        );
+
+        handle.set_skip_rules([]).unwrap();
+        info!(body = "big", "after");
+    });
+    assert!(handle
+        .set_skip_rules([SkipRule::new("*").for_target("e2e")])
+        .is_err());
+
+    let tracing_output = parse_output(&make_writer);
+    let before = &tracing_output[1];
+    assert!(before.get("body").is_none());
+    assert_eq!(before.get("payload"), Some(&json!("big")));
+    assert!(before.get("internal.retries").is_none());
+    let after = &tracing_output[2];
+    assert_eq!(after.get("body"), Some(&json!("big")));
+}
+
+#[test]
+fn configuration_can_be_changed_at_runtime() {
+    let make_writer = MockMakeWriter::default();
+    let formatting_layer = BunyanFormattingLayer::new("test".into(), make_writer.clone())
+        .skip_fields(vec!["payload"].into_iter())
+        .unwrap();
+    let handle = formatting_layer.config_handle();
+    let subscriber = Registry::default()
+        .with(JsonStorageLayer)
+        .with(formatting_layer);
+    tracing::subscriber::with_default(subscriber, || {
+        let span = span!(Level::DEBUG, "request", request_id = 1);
+        let _enter = span.enter();
+        info!(payload = "big", internal.retries = 1, "before");
+
+        handle.set_serialize_span_fields(false);
+        handle.set_serialize_span_id(true);
+        handle.set_skip_fields(["internal.*"]).unwrap();
+        handle.set_default_fields(HashMap::from([("deployment".to_owned(), json!("green"))]));
+        info!(payload = "big", internal.retries = 1, "after");
+    });
+    assert!(handle.set_skip_fields(["msg"]).is_err());
+
+    let tracing_output = parse_output(&make_writer);
+    let before = &tracing_output[1];
+    assert_eq!(before.get("request_id"), Some(&json!(1)));
+    assert!(before.get("payload").is_none());
+    assert_eq!(before.get("internal.retries"), Some(&json!(1)));
+    assert!(before.get("span_id").is_none());
+    let after = &tracing_output[2];
+    assert!(after.get("request_id").is_none());
+    assert_eq!(after.get("payload"), Some(&json!("big")));
+    assert!(after.get("internal.retries").is_none());
+    assert!(after.get("span_id").is_some());
+    assert_eq!(after.get("deployment"), Some(&json!("green")));
+}
+
+#[test]
+fn non_blocking_writer_flushes_records_when_the_guard_is_dropped() {
+    let make_writer = MockMakeWriter::default();
+    let (writer, guard) = NonBlockingBuilder::default().finish(make_writer.make_writer());
+    let formatting_layer = BunyanFormattingLayer::new("test".into(), writer);
+    let subscriber = Registry::default()
+        .with(JsonStorageLayer)
+        .with(formatting_layer);
+    tracing::subscriber::with_default(subscriber, || {
+        for i in 0..100 {
+            info!(i, "Record");
+        }
+    });
+    drop(guard);
+
+    let tracing_output = parse_output(&make_writer);
+    assert_eq!(tracing_output.len(), 100);
+    for (i, record) in tracing_output.iter().enumerate() {
+        assert_eq!(record["i"], json!(i));
+    }
+}
+
+// A writer blocking on its first record until released, to fill the queue of a non-blocking
+// writer deterministically.
+struct StalledWriter {
+    inner: MockWriter,
+    started: Option<std::sync::mpsc::Sender<()>>,
+    release: std::sync::mpsc::Receiver<()>,
+}
+
+impl std::io::Write for StalledWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if let Some(started) = self.started.take() {
+            started.send(()).unwrap();
+            self.release.recv().unwrap();
+        }
+        self.inner.write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+fn run_with_full_queue(policy: OverflowPolicy) -> (Vec<String>, u64) {
+    use std::io::Write;
+
+    let make_writer = MockMakeWriter::default();
+    let (started_tx, started_rx) = std::sync::mpsc::channel();
+    let (release_tx, release_rx) = std::sync::mpsc::channel();
+    let stalled_writer = StalledWriter {
+        inner: make_writer.make_writer(),
+        started: Some(started_tx),
+        release: release_rx,
+    };
+    let (mut writer, guard) = NonBlockingBuilder::default()
+        .buffered_records_limit(1)
+        .overflow_policy(policy)
+        .finish(stalled_writer);
+    writer.write_all(b"1\n").unwrap();
+    started_rx.recv().unwrap();
+    for record in [&b"2\n"[..], b"3\n", b"4\n"] {
+        writer.write_all(record).unwrap();
+    }
+    release_tx.send(()).unwrap();
+    drop(guard);
+
+    let records = make_writer.get_string().lines().map(String::from).collect();
+    (records, writer.dropped_records())
+}
+
+#[test]
+fn non_blocking_writer_drops_the_newest_records_when_full() {
+    assert_eq!(
+        run_with_full_queue(OverflowPolicy::DropNewest),
+        (vec!["1".to_owned(), "2".to_owned()], 2)
+    );
+}
+
+#[test]
+fn non_blocking_writer_drops_the_oldest_records_when_full() {
+    assert_eq!(
+        run_with_full_queue(OverflowPolicy::DropOldest),
+        (vec!["1".to_owned(), "4".to_owned()], 2)
+    );
+}
+
+// A writer failing like a full disk would.
+struct FullDiskWriter;
+
+impl std::io::Write for FullDiskWriter {
+    fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+        Err(std::io::Error::new(
+            std::io::ErrorKind::Other,
+            "No space left on device",
+        ))
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+// A writer panicking on its first write.
+struct PanickingWriter;
+
+impl std::io::Write for PanickingWriter {
+    fn write(&mut self, _buf: &[u8]) -> std::io::Result<usize> {
+        panic!("The writer panicked");
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[test]
+fn a_panicking_writer_does_not_block_logging_threads() {
+    use std::io::Write;
+
+    let (mut writer, guard) = NonBlockingBuilder::default()
+        .buffered_records_limit(1)
+        .overflow_policy(OverflowPolicy::Block)
+        .finish(PanickingWriter);
+    for record in [&b"1\n"[..], b"2\n", b"3\n", b"4\n"] {
+        writer.write_all(record).unwrap();
+    }
+    drop(guard);
+
+    // The first record was being written when the writer panicked.
+    assert_eq!(writer.dropped_records(), 3);
+}
+
+#[test]
+fn non_blocking_writer_reports_write_errors() {
+    use std::io::Write;
+
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let (mut writer, guard) = NonBlockingBuilder::default()
+        .write_error_handler({
+            let errors = errors.clone();
+            move |error: &std::io::Error| errors.lock().unwrap().push(error.to_string())
+        })
+        .finish(FullDiskWriter);
+    writer.write_all(b"1\n").unwrap();
+    writer.write_all(b"2\n").unwrap();
+    drop(guard);
+
+    assert_eq!(writer.write_errors(), 2);
+    assert_eq!(
+        *errors.lock().unwrap(),
+        vec!["No space left on device", "No space left on device"]
+    );
+}
+
+fn run_with_failing_writer(fallback_writer: Option<MockMakeWriter>) -> Vec<String> {
+    let errors = Arc::new(Mutex::new(Vec::new()));
+    let mut builder = BunyanFormattingLayer::builder("test")
+        .writer(|| FullDiskWriter)
+        .error_handler({
+            let errors = errors.clone();
+            move |error: &std::io::Error, metadata: &tracing::Metadata<'_>| {
+                errors
+                    .lock()
+                    .unwrap()
+                    .push(format!("{}: {}", metadata.name(), error));
+            }
+        });
+    if let Some(fallback_writer) = fallback_writer {
+        builder = builder.fallback_writer(fallback_writer);
+    }
+    let subscriber = Registry::default()
+        .with(JsonStorageLayer)
+        .with(builder.build().unwrap());
+    tracing::subscriber::with_default(subscriber, || {
+        let span = span!(Level::DEBUG, "request");
+        let _enter = span.enter();
+        info!("Record");
+    });
+
+    let errors = errors.lock().unwrap();
+    errors.clone()
+}
+
+#[test]
+fn write_errors_are_reported_to_the_error_handler() {
+    let errors = run_with_failing_writer(None);
+    assert_eq!(errors.len(), 3);
+    assert_eq!(errors[0], "request: No space left on device");
+    assert!(errors[1].ends_with(": No space left on device"));
+    assert_eq!(errors[2], "request: No space left on device");
+}
+
+#[test]
+fn records_the_writer_fails_to_write_go_to_the_fallback_writer() {
+    let fallback_writer = MockMakeWriter::default();
+    let errors = run_with_failing_writer(Some(fallback_writer.clone()));
+    assert_eq!(errors.len(), 3);
+
+    let tracing_output = parse_output(&fallback_writer);
+    assert_eq!(tracing_output.len(), 3);
+    assert_eq!(tracing_output[0]["msg"], json!("[REQUEST - START]"));
+    assert_eq!(tracing_output[1]["msg"], json!("[REQUEST - EVENT] Record"));
+    assert_eq!(tracing_output[2]["msg"], json!("[REQUEST - END]"));
+}
+
+#[test]
+fn stats_count_the_records_written() {
+    let make_writer = MockMakeWriter::default();
+    let formatting_layer = BunyanFormattingLayer::builder("test")
+        .writer(make_writer.clone())
+        .span_events(SpanEvents::ALL)
+        .fatal_field("fatal")
+        .build()
+        .unwrap();
+    let stats = formatting_layer.stats_handle();
+    let subscriber = Registry::default()
+        .with(JsonStorageLayer)
+        .with(formatting_layer);
+    tracing::subscriber::with_default(subscriber, || {
+        let span = span!(Level::DEBUG, "request");
+        let _enter = span.enter();
+        info!("Hello");
+        tracing::warn!("Careful");
+        tracing::error!(fatal = true, "Goodbye");
+    });
+
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.records_by_level.debug, 4);
+    assert_eq!(snapshot.records_by_level.info, 1);
+    assert_eq!(snapshot.records_by_level.warn, 1);
+    assert_eq!(snapshot.records_by_level.error, 0);
+    assert_eq!(snapshot.records_by_level.fatal, 1);
+    assert_eq!(snapshot.records_by_type.start, 1);
+    assert_eq!(snapshot.records_by_type.enter, 1);
+    assert_eq!(snapshot.records_by_type.exit, 1);
+    assert_eq!(snapshot.records_by_type.end, 1);
+    assert_eq!(snapshot.records_by_type.event, 3);
+    assert_eq!(snapshot.records_written(), 7);
+    assert_eq!(
+        snapshot.bytes_written,
+        make_writer.get_string().len() as u64
+    );
+    assert_eq!(snapshot.write_errors, 0);
+    assert_eq!(snapshot.dropped_records, 0);
+}
+
+#[test]
+fn stats_count_write_errors_and_dropped_records() {
+    let formatting_layer = BunyanFormattingLayer::builder("test")
+        .writer(|| FullDiskWriter)
+        .build()
+        .unwrap();
+    let stats = formatting_layer.stats_handle();
+    let subscriber = Registry::default().with(formatting_layer);
+    tracing::subscriber::with_default(subscriber, || {
+        info!("Lost");
+    });
+
+    let snapshot = stats.snapshot();
+    assert_eq!(snapshot.records_written(), 0);
+    assert_eq!(snapshot.bytes_written, 0);
+    assert_eq!(snapshot.write_errors, 1);
+    assert_eq!(snapshot.dropped_records, 1);
+    assert_eq!(snapshot.serialization_errors, 0);
+}
+
 #[cfg(feature = "valuable")]
 mod valuable_tests {
     use super::run_and_get_output;
@@ -305,3 +1623,185 @@ mod valuable_tests {
         );
     }
 }
+
+#[cfg(feature = "redaction")]
+mod redaction_tests {
+    use super::run_with_layer;
+    use serde_json::{json, Value};
+    use tracing::{info, span, Level};
+    use tracing_bunyan_formatter::{
+        BunyanFormattingLayer, ConfigError, FieldPattern, MessageFallback, Redaction,
+        BEARER_TOKEN_PATTERN, EMAIL_PATTERN,
+    };
+
+    #[test]
+    fn fields_and_values_are_redacted() {
+        let action = || {
+            let span = span!(Level::DEBUG, "request", session_token = "abc");
+            let _enter = span.enter();
+
+            info!(
+                password = "hunter2",
+                card_number = 4111111111111234u64,
+                header = "Authorization: Bearer eyJhbGciOi.payload",
+                "Login from john@example.com"
+            );
+        };
+        let tracing_output = run_with_layer(
+            |make_writer| {
+                BunyanFormattingLayer::builder("test")
+                    .writer(make_writer)
+                    .default_field("api_key", json!("secret"))
+                    .redact_field(FieldPattern::exact("password"), Redaction::Replace)
+                    .redact_field(FieldPattern::exact("api_key"), Redaction::Replace)
+                    .redact_field(FieldPattern::glob("*_token"), Redaction::Hash)
+                    .redact_field(
+                        FieldPattern::regex("(?i)^card"),
+                        Redaction::Mask { visible: 4 },
+                    )
+                    .redact_values(BEARER_TOKEN_PATTERN, Redaction::Replace)
+                    .redact_values(EMAIL_PATTERN, Redaction::Replace)
+                    .build()
+                    .unwrap()
+            },
+            action,
+        );
+
+        let event = tracing_output
+            .iter()
+            .find(|record| record.get("password").is_some())
+            .unwrap();
+        assert_eq!(event.get("password"), Some(&json!("[REDACTED]")));
+        assert_eq!(event.get("api_key"), Some(&json!("[REDACTED]")));
+        assert_eq!(event.get("card_number"), Some(&json!("************1234")));
+        assert_eq!(
+            event.get("header"),
+            Some(&json!("Authorization: [REDACTED]"))
+        );
+        assert_eq!(
+            event.get("msg"),
+            Some(&json!("[REQUEST - EVENT] Login from [REDACTED]"))
+        );
+        let session_token = event.get("session_token").and_then(Value::as_str).unwrap();
+        assert!(session_token.starts_with("[HASH:"));
+        assert_ne!(session_token, "abc");
+
+        // Span records are redacted too
+        for record in &tracing_output {
+            assert_eq!(record.get("session_token"), Some(&json!(session_token)));
+        }
+    }
+
+    #[test]
+    fn span_list_fields_are_redacted_once() {
+        let tracing_output = run_with_layer(
+            |make_writer| {
+                BunyanFormattingLayer::builder("test")
+                    .writer(make_writer)
+                    .serialize_span_list(true)
+                    .redact_field(FieldPattern::exact("token"), Redaction::Hash)
+                    .redact_field(FieldPattern::exact("card"), Redaction::Mask { visible: 4 })
+                    .build()
+                    .unwrap()
+            },
+            || {
+                let span = span!(
+                    Level::DEBUG,
+                    "request",
+                    token = "abc",
+                    card = "4111111111111234"
+                );
+                let _enter = span.enter();
+                info!("Hello");
+            },
+        );
+
+        let event = tracing_output
+            .iter()
+            .find(|record| record.get("spans").is_some())
+            .unwrap();
+        let fields = &event["spans"][0]["fields"];
+        assert!(event["token"].as_str().unwrap().starts_with("[HASH:"));
+        assert_eq!(fields["token"], event["token"]);
+        assert_eq!(event["card"], json!("************1234"));
+        assert_eq!(fields["card"], event["card"]);
+    }
+
+    #[test]
+    fn invalid_redaction_patterns_are_reported() {
+        let result = BunyanFormattingLayer::builder("test")
+            .redact_field(FieldPattern::regex("("), Redaction::Replace)
+            .redact_values("[", Redaction::Replace)
+            .build();
+
+        let errors = match result {
+            Err(err) => err.errors().to_vec(),
+            Ok(_) => panic!("invalid redaction patterns shouldn't build"),
+        };
+        assert_eq!(errors.len(), 2);
+        assert!(errors
+            .iter()
+            .all(|e| matches!(e, ConfigError::InvalidRedactionPattern { .. })));
+    }
+
+    #[test]
+    fn rendered_fields_fallback_message_is_redacted() {
+        let tracing_output = run_with_layer(
+            |make_writer| {
+                BunyanFormattingLayer::builder("test")
+                    .writer(make_writer)
+                    .message_fallback(MessageFallback::Fields)
+                    .redact_field(FieldPattern::exact("password"), Redaction::Replace)
+                    .build()
+                    .unwrap()
+            },
+            || info!(user = "ferris", password = "hunter2"),
+        );
+
+        assert_eq!(
+            tracing_output[0].get("msg"),
+            Some(&json!("password=[REDACTED] user=ferris"))
+        );
+    }
+
+    #[test]
+    fn non_string_messages_are_redacted_like_string_messages() {
+        let tracing_output = run_with_layer(
+            |make_writer| {
+                BunyanFormattingLayer::builder("test")
+                    .writer(make_writer)
+                    .redact_field(FieldPattern::exact("message"), Redaction::Replace)
+                    .redact_values(r"[0-9]+", Redaction::Hash)
+                    .build()
+                    .unwrap()
+            },
+            || {
+                info!(message = 42);
+                info!("42");
+            },
+        );
+
+        let msg = tracing_output[0]["msg"].as_str().unwrap();
+        assert!(msg.starts_with("[HASH:"));
+        assert_eq!(tracing_output[1]["msg"], json!(msg));
+    }
+
+    #[test]
+    fn rendered_fields_fallback_message_is_redacted_once() {
+        let tracing_output = run_with_layer(
+            |make_writer| {
+                BunyanFormattingLayer::builder("test")
+                    .writer(make_writer)
+                    .message_fallback(MessageFallback::Fields)
+                    .redact_values(r"[0-9]+", Redaction::Hash)
+                    .build()
+                    .unwrap()
+            },
+            || info!(code = "abc 42"),
+        );
+
+        let code = tracing_output[0]["code"].as_str().unwrap();
+        assert!(code.starts_with("abc [HASH:"));
+        assert_eq!(tracing_output[0]["msg"], json!(format!("code={}", code)));
+    }
+}
@@ -1,5 +1,6 @@
 use std::io;
 use std::sync::{Arc, Mutex, MutexGuard, TryLockError};
+use tracing_subscriber::fmt::MakeWriter;
 
 /// Use a vector of bytes behind a Arc<Mutex> as writer in order to inspect the tracing output
 /// for testing purposes.
@@ -34,3 +35,25 @@ impl io::Write for MockWriter {
         self.buf()?.flush()
     }
 }
+
+/// A `MakeWriter` handing out `MockWriter`s that all share the same in-memory buffer.
+#[derive(Clone, Default)]
+pub struct MockMakeWriter {
+    buf: Arc<Mutex<Vec<u8>>>,
+}
+
+impl MockMakeWriter {
+    /// Get the content of the shared buffer as a string.
+    pub fn get_string(&self) -> String {
+        let buf = self.buf.lock().unwrap();
+        String::from_utf8(buf.to_vec()).unwrap()
+    }
+}
+
+impl<'a> MakeWriter<'a> for MockMakeWriter {
+    type Writer = MockWriter;
+
+    fn make_writer(&'a self) -> Self::Writer {
+        MockWriter::new(self.buf.clone())
+    }
+}